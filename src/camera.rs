@@ -0,0 +1,357 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use bevy::{
+    input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit},
+    prelude::{
+        error, info, ButtonInput, Component, Entity, GlobalTransform, Interaction, KeyCode,
+        MouseButton,
+        OrthographicProjection, PerspectiveProjection, Projection, Quat, Query, Res, Time,
+        Transform, Vec2, Vec3, With,
+    },
+    render::camera::ScalingMode,
+};
+
+use crate::{CrabName, MainCamera, Pen, PenHandle, Sketch, Stroke, StrokeSegment};
+
+pub(crate) const FRAME_ALL_KEY: KeyCode = KeyCode::KeyF;
+pub(crate) const TOGGLE_PROJECTION_KEY: KeyCode = KeyCode::KeyP;
+pub(crate) const CYCLE_FOLLOW_KEY: KeyCode = KeyCode::Tab;
+
+/// Radians of orbit for each pixel that the mouse is dragged.
+const ORBIT_MOUSE_RATE: f32 = 0.005;
+/// Radians of orbit per second while an arrow key is held.
+const ORBIT_KEY_RATE: f32 = std::f32::consts::FRAC_PI_2;
+/// Fraction of the camera distance panned for each pixel that the mouse is
+/// dragged.
+const PAN_MOUSE_RATE: f32 = 0.002;
+/// Fraction of the camera distance panned per second while a key is held.
+const PAN_KEY_RATE: f32 = 1.0;
+/// How much each line of scrolling zooms in or out.
+const ZOOM_SCROLL_RATE: f32 = 0.1;
+/// How much zooming happens per second while a key is held.
+const ZOOM_KEY_RATE: f32 = 1.0;
+/// Pixels of smooth scrolling that count as one line of a mouse wheel.
+const PIXELS_PER_SCROLL_LINE: f32 = 100.0;
+/// Leave some room around the strokes when framing them.
+const FRAME_MARGIN: f32 = 1.2;
+const MIN_DISTANCE: f32 = 1e-3;
+/// How close the camera may come to looking straight down or straight up, in
+/// radians.
+const MIN_TILT: f32 = 1e-3;
+/// Stop chasing a pen once the focus is this close to it, relative to the
+/// camera distance.
+const FOLLOW_TOLERANCE: f32 = 1e-4;
+
+/// How the camera keeps up with a pen that it follows. Use it with
+/// [`Sketch::follow_with`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraFollow {
+    /// Where the camera looks relative to the pen.
+    pub offset: Vec3,
+    /// Roughly how many seconds the camera lags behind the pen. Use zero to
+    /// stay locked onto it.
+    pub smoothing: f32,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        Self {
+            offset: Vec3::ZERO,
+            smoothing: 0.3,
+        }
+    }
+}
+
+impl Sketch {
+    /// Keep the camera looking at a pen as it moves. Press `Tab` while the
+    /// sketch runs to switch between crabs.
+    pub fn follow(&mut self, pen: PenHandle) -> &mut Self {
+        self.follow_with(pen, CameraFollow::default())
+    }
+
+    pub fn follow_with(&mut self, pen: PenHandle, follow: CameraFollow) -> &mut Self {
+        let world = self.app.world_mut();
+        let Some(main_camera) = world.get_resource::<MainCamera>() else {
+            error!("Cannot follow a pen in a sketch without a camera");
+            return self;
+        };
+
+        if let Some(mut controls) = world.get_mut::<CameraControls>(main_camera.entity) {
+            controls.following = Some(pen.0);
+            controls.follow = follow;
+        }
+        self
+    }
+}
+
+/// Lets the user orbit, pan, and zoom the main camera around a focal point.
+///
+/// * Orbit: drag with the left mouse button, or use the arrow keys
+/// * Pan: drag with the right or middle mouse button, or hold shift with the
+///   arrow keys
+/// * Zoom: scroll, or use the `+` and `-` keys
+/// * Frame everything that has been drawn: `F`
+/// * Toggle between perspective and orthographic: `P`
+/// * Follow the next crab, sorted by name, or go back to a free camera: `Tab`
+#[derive(Component, Debug, Clone, Copy)]
+pub(crate) struct CameraControls {
+    pub(crate) focus: Vec3,
+    pub(crate) distance: f32,
+    /// Field of view to go back to when leaving orthographic projection.
+    pub(crate) fov: f32,
+    pub(crate) following: Option<Entity>,
+    pub(crate) follow: CameraFollow,
+}
+
+impl CameraControls {
+    pub(crate) fn new(focus: Vec3, distance: f32) -> Self {
+        Self {
+            focus,
+            distance,
+            fov: PerspectiveProjection::default().fov,
+            following: None,
+            follow: CameraFollow::default(),
+        }
+    }
+
+    pub(crate) fn place(&self, tf: &mut Transform) {
+        tf.translation = self.focus + tf.rotation * (self.distance * Vec3::Z);
+    }
+
+    /// Fit a sphere with the given center and radius inside the view.
+    pub(crate) fn frame(&mut self, center: Vec3, radius: f32) {
+        self.focus = center;
+        self.distance = (FRAME_MARGIN * radius / (self.fov / 2.0).sin()).max(MIN_DISTANCE);
+    }
+
+    fn sync_projection(&self, projection: &mut Projection) {
+        match projection {
+            Projection::Perspective(perspective) => {
+                // Keep the near plane in proportion so that small sketches do
+                // not get clipped when zooming in on them.
+                perspective.near = (0.01 * self.distance).min(0.1);
+            }
+            Projection::Orthographic(orthographic) => {
+                // Match the size of the focal plane in perspective so toggling
+                // does not jump.
+                orthographic.scaling_mode = ScalingMode::FixedVertical {
+                    viewport_height: 2.0 * self.distance * (self.fov / 2.0).tan(),
+                };
+                orthographic.far = 1000.0 * self.distance.max(1.0);
+            }
+        }
+    }
+
+    fn toggle_projection(&self, projection: &mut Projection) {
+        *projection = match projection {
+            Projection::Perspective(_) => Projection::Orthographic(OrthographicProjection {
+                near: -1000.0 * self.distance.max(1.0),
+                ..OrthographicProjection::default_3d()
+            }),
+            Projection::Orthographic(_) => Projection::Perspective(PerspectiveProjection {
+                fov: self.fov,
+                ..Default::default()
+            }),
+        };
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn control_camera(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    motion: Res<AccumulatedMouseMotion>,
+    scroll: Res<AccumulatedMouseScroll>,
+    time: Res<Time>,
+    main_camera: Res<MainCamera>,
+    mut cameras: Query<(&mut CameraControls, &mut Transform, &mut Projection)>,
+    strokes: Query<&StrokeSegment>,
+    pens: Query<&GlobalTransform, With<Pen>>,
+    crabs: Query<(Entity, &CrabName)>,
+    widgets: Query<&Interaction>,
+) {
+    let Ok((mut controls, mut tf, mut projection)) = cameras.get_mut(main_camera.entity) else {
+        return;
+    };
+
+    let dt = time.delta_secs();
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let arrows = Vec2::new(
+        axis(&keyboard, KeyCode::ArrowLeft, KeyCode::ArrowRight),
+        axis(&keyboard, KeyCode::ArrowDown, KeyCode::ArrowUp),
+    );
+
+    let mut orbit = Vec2::ZERO;
+    let mut pan = Vec2::ZERO;
+    // Dragging on the on-screen widgets should not move the camera.
+    let on_widget = widgets.iter().any(|i| *i != Interaction::None);
+    if mouse.pressed(MouseButton::Left) && !on_widget {
+        orbit += ORBIT_MOUSE_RATE * motion.delta;
+    }
+    if mouse.any_pressed([MouseButton::Right, MouseButton::Middle]) && !on_widget {
+        pan += PAN_MOUSE_RATE * motion.delta;
+    }
+    if shift {
+        pan -= PAN_KEY_RATE * dt * arrows * Vec2::new(1.0, -1.0);
+    } else {
+        orbit -= ORBIT_KEY_RATE * dt * arrows * Vec2::new(1.0, -1.0);
+    }
+
+    let mut zoom = ZOOM_KEY_RATE * dt * axis(
+        &keyboard,
+        KeyCode::Minus,
+        KeyCode::Equal,
+    );
+    zoom += ZOOM_SCROLL_RATE * match scroll.unit {
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / PIXELS_PER_SCROLL_LINE,
+    };
+
+    let frame_all = keyboard.just_pressed(FRAME_ALL_KEY);
+    let toggle = keyboard.just_pressed(TOGGLE_PROJECTION_KEY);
+
+    if keyboard.just_pressed(CYCLE_FOLLOW_KEY) {
+        let mut names: Vec<(Entity, &CrabName)> = crabs.iter().collect();
+        names.sort_by(|(a, a_name), (b, b_name)| a_name.0.cmp(&b_name.0).then(a.cmp(b)));
+
+        // Go through every crab in turn and then back to a free camera.
+        let next = match controls.following {
+            Some(current) => names
+                .iter()
+                .position(|(e, _)| *e == current)
+                .and_then(|i| names.get(i + 1)),
+            None => names.first(),
+        };
+
+        match next {
+            Some((e, name)) => {
+                info!("Following crab [{}]", name.0);
+                controls.following = Some(*e);
+            }
+            None => {
+                info!("Free camera");
+                controls.following = None;
+            }
+        }
+    }
+
+    // Framing everything is a request to stop chasing a single crab.
+    if frame_all {
+        controls.following = None;
+    }
+
+    let mut chase = Vec3::ZERO;
+    if let Some(pen) = controls.following {
+        match pens.get(pen) {
+            Ok(pen_tf) => {
+                let target = pen_tf.translation() + controls.follow.offset;
+                let gap = target - controls.focus;
+                if gap.length() > FOLLOW_TOLERANCE * controls.distance {
+                    let blend = if controls.follow.smoothing > 0.0 {
+                        1.0 - (-dt / controls.follow.smoothing).exp()
+                    } else {
+                        1.0
+                    };
+                    chase = blend * gap;
+                }
+            }
+            Err(_) => controls.following = None,
+        }
+    }
+
+    let idle = orbit == Vec2::ZERO && pan == Vec2::ZERO && zoom == 0.0 && chase == Vec3::ZERO;
+    if idle && !frame_all && !toggle {
+        // Leave the camera untouched so that screen-space strokes are only
+        // rebuilt when the view really changes.
+        return;
+    }
+
+    if orbit != Vec2::ZERO {
+        // Yaw around the vertical axis of the sketch and pitch around the
+        // horizontal axis of the view so the horizon never rolls. Pitching
+        // stops just short of looking straight up or down so that the view
+        // never flips over the top.
+        let right = tf.rotation * Vec3::X;
+        let yaw = right.y.atan2(right.x) - orbit.x;
+        let tilt = (tf.rotation * Vec3::Z).z.clamp(-1.0, 1.0).acos() - orbit.y;
+        let tilt = tilt.clamp(MIN_TILT, std::f32::consts::PI - MIN_TILT);
+        tf.rotation = Quat::from_rotation_z(yaw) * Quat::from_rotation_x(tilt);
+    }
+
+    if pan != Vec2::ZERO {
+        let offset = controls.distance * (tf.rotation * Vec3::new(-pan.x, pan.y, 0.0));
+        controls.focus += offset;
+        if controls.following.is_some() {
+            // Panning while following moves where the camera looks relative
+            // to the pen.
+            controls.follow.offset += offset;
+        }
+    }
+
+    controls.focus += chase;
+
+    if zoom != 0.0 {
+        controls.distance = (controls.distance * (-zoom).exp()).max(MIN_DISTANCE);
+    }
+
+    if frame_all {
+        let mut min = Vec3::INFINITY;
+        let mut max = Vec3::NEG_INFINITY;
+        for segment in &strokes {
+            if segment.progress <= 0.0 {
+                continue;
+            }
+
+            let margin = match segment.stroke {
+                Stroke::Volume(width) | Stroke::Ribbon(width) => width / 2.0,
+                Stroke::Pixels(_) => 0.0,
+            };
+            for p in segment.path.iter().map(|tf| tf.translation) {
+                min = min.min(p - margin);
+                max = max.max(p + margin);
+            }
+        }
+
+        for p in pens.iter().map(GlobalTransform::translation) {
+            min = min.min(p);
+            max = max.max(p);
+        }
+
+        if min.cmple(max).all() {
+            controls.frame((min + max) / 2.0, (max - min).length() / 2.0);
+        }
+    }
+
+    if toggle {
+        controls.toggle_projection(&mut projection);
+    }
+
+    controls.place(&mut tf);
+    controls.sync_projection(&mut projection);
+}
+
+fn axis(keyboard: &ButtonInput<KeyCode>, negative: KeyCode, positive: KeyCode) -> f32 {
+    let mut value = 0.0;
+    if keyboard.pressed(negative) {
+        value -= 1.0;
+    }
+    if keyboard.pressed(positive) {
+        value += 1.0;
+    }
+    value
+}
@@ -0,0 +1,281 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{
+    prelude::{
+        error, info, AppExit, Camera, Commands, EventWriter, Image, IntoSystemConfigs, Query,
+        Res, ResMut, Resource, Trigger, Update,
+    },
+    render::{
+        camera::RenderTarget,
+        render_resource::PipelineCache,
+        view::screenshot::{Screenshot, ScreenshotCaptured},
+        ExtractSchedule, MainWorld, RenderApp, RenderPlugin,
+    },
+};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame, ImageResult, RgbImage,
+};
+
+use crate::{play_schedule, MainCamera, Playback, Sketch, Timeline};
+
+/// Render pipelines are compiled in the background, so the final picture is
+/// only taken once none are left waiting for this many frames in a row.
+const CAPTURE_SETTLE_FRAMES: u32 = 10;
+
+/// Give up on waiting for render pipelines after this many frames and take the
+/// final picture anyway.
+const CAPTURE_MAX_WAIT_FRAMES: u32 = 600;
+
+/// How long the last frame of an animated GIF stays up before it loops.
+const GIF_HOLD_SECONDS: f32 = 2.0;
+
+/// Save pictures of a sketch while it plays. Use it with [`Sketch::capture`].
+#[derive(Debug, Clone)]
+pub struct Capture {
+    /// Folder where the pictures get saved. The finished drawing is saved as
+    /// `final.png`.
+    pub directory: PathBuf,
+    /// Also save `frame_#####.png` every N frames while the sketch plays. Zero
+    /// saves no frames, the same as [`None`].
+    pub every_n_frames: Option<u32>,
+    /// Assemble every picture into an animated `capture.gif`.
+    pub gif: bool,
+    /// Close the app once the finished drawing has been saved.
+    pub exit_when_done: bool,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("capture"),
+            every_n_frames: None,
+            gif: false,
+            exit_when_done: true,
+        }
+    }
+}
+
+impl Capture {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn every_n_frames(mut self, n: u32) -> Self {
+        self.every_n_frames = Some(n);
+        self
+    }
+
+    pub fn with_gif(mut self) -> Self {
+        self.gif = true;
+        self
+    }
+
+    fn is_frame_due(&self, frame: u32) -> bool {
+        self.every_n_frames.is_some_and(|n| n != 0 && frame % n == 0)
+    }
+}
+
+impl Sketch {
+    /// Save pictures of what [`crate::MainCamera`] sees while the sketch runs.
+    /// This needs a sketch that renders, either [`Sketch::new`] or
+    /// [`Sketch::offscreen`].
+    pub fn capture(&mut self, capture: Capture) -> &mut Self {
+        if !self.app.is_plugin_added::<RenderPlugin>() {
+            error!("Cannot capture pictures of a sketch that does not render");
+            return self;
+        }
+
+        self.app
+            .insert_resource(CaptureState {
+                capture,
+                frame: 0,
+                settle: 0,
+                waited: 0,
+                pipelines_ready: false,
+                pending: 0,
+                done: false,
+                frames: Vec::new(),
+            })
+            .add_systems(Update, capture_frames.after(play_schedule));
+        self.app
+            .sub_app_mut(RenderApp)
+            .add_systems(ExtractSchedule, report_pipelines_ready);
+        self
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct CaptureState {
+    capture: Capture,
+    frame: u32,
+    settle: u32,
+    waited: u32,
+    pipelines_ready: bool,
+    pending: u32,
+    done: bool,
+    frames: Vec<(f32, RgbImage)>,
+}
+
+impl CaptureState {
+    fn save(&mut self, image: Image, path: &Path, time: f32) -> ImageResult<()> {
+        let image = image.try_into_dynamic().map_err(|err| {
+            image::ImageError::IoError(std::io::Error::other(err.to_string()))
+        })?;
+
+        // The alpha channel holds brightness values when HDR is on, so it
+        // should not be treated as transparency.
+        let image = image.to_rgb8();
+        std::fs::create_dir_all(&self.capture.directory)?;
+        image.save(path)?;
+        info!("Saved {}", path.display());
+
+        if self.capture.gif {
+            self.frames.push((time, image));
+        }
+
+        Ok(())
+    }
+
+    fn write_gif(&mut self) -> ImageResult<()> {
+        let mut frames = std::mem::take(&mut self.frames);
+        frames.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let path = self.capture.directory.join("capture.gif");
+        let mut encoder = GifEncoder::new_with_speed(BufWriter::new(File::create(&path)?), 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (i, (time, image)) in frames.iter().enumerate() {
+            // Each frame stays up for as long as the playback took to reach the
+            // next one, so the animation keeps the pace of the sketch.
+            let next = frames.get(i + 1).map_or(time + GIF_HOLD_SECONDS, |(t, _)| *t);
+            let delay = Duration::from_secs_f32((next - time).max(0.01));
+            let image = DynamicImage::ImageRgb8(image.clone()).into_rgba8();
+            encoder.encode_frame(Frame::from_parts(
+                image,
+                0,
+                0,
+                Delay::from_saturating_duration(delay),
+            ))?;
+        }
+
+        info!("Saved {}", path.display());
+        Ok(())
+    }
+}
+
+fn report_pipelines_ready(pipelines: Res<PipelineCache>, mut main_world: ResMut<MainWorld>) {
+    if let Some(mut state) = main_world.get_resource_mut::<CaptureState>() {
+        state.pipelines_ready = pipelines.waiting_pipelines().next().is_none();
+    }
+}
+
+fn capture_frames(
+    mut commands: Commands,
+    mut state: ResMut<CaptureState>,
+    playback: Res<Playback>,
+    timeline: Res<Timeline>,
+    main_camera: Res<MainCamera>,
+    cameras: Query<&Camera>,
+) {
+    if state.done {
+        return;
+    }
+    state.frame += 1;
+    let frame = state.frame;
+
+    let finished = playback.time >= timeline.duration();
+    let (is_final, name) = if finished {
+        state.waited += 1;
+        state.settle = if state.pipelines_ready { state.settle + 1 } else { 0 };
+        let settled = state.settle > CAPTURE_SETTLE_FRAMES || state.waited > CAPTURE_MAX_WAIT_FRAMES;
+        (settled, "final.png".to_owned())
+    } else {
+        if !state.capture.is_frame_due(frame) {
+            return;
+        }
+        (false, format!("frame_{frame:05}.png"))
+    };
+
+    if finished && !is_final {
+        return;
+    }
+
+    let screenshot = match cameras.get(main_camera.entity).map(|camera| &camera.target) {
+        Ok(RenderTarget::Image(image)) => Screenshot::image(image.clone()),
+        _ => Screenshot::primary_window(),
+    };
+
+    let path = state.capture.directory.join(name);
+    let time = playback.time;
+    state.pending += 1;
+    state.done = is_final;
+
+    commands.spawn(screenshot).observe(
+        move |trigger: Trigger<ScreenshotCaptured>,
+              mut state: ResMut<CaptureState>,
+              mut exit: EventWriter<AppExit>| {
+            if let Err(err) = state.save(trigger.event().0.clone(), &path, time) {
+                error!("Cannot save {}: {err}", path.display());
+            }
+
+            state.pending -= 1;
+            if !state.done || state.pending > 0 {
+                return;
+            }
+
+            if state.capture.gif {
+                if let Err(err) = state.write_gif() {
+                    error!("Cannot save animated gif: {err}");
+                }
+            }
+
+            if state.capture.exit_when_done {
+                exit.send(AppExit::Success);
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_due_every_n_frames() {
+        let capture = Capture::default().every_n_frames(3);
+        let due: Vec<u32> = (1..=10).filter(|frame| capture.is_frame_due(*frame)).collect();
+        assert_eq!(due, [3, 6, 9]);
+        assert!(!(1..=10).any(|frame| Capture::default().is_frame_due(frame)));
+    }
+
+    #[test]
+    fn zero_frames_saves_none_instead_of_dividing_by_zero() {
+        let capture = Capture::default().every_n_frames(0);
+        assert!(!(0..=10).any(|frame| capture.is_frame_due(frame)));
+    }
+}
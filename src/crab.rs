@@ -18,34 +18,59 @@
 use bevy::{
     prelude::{
         Component, Entity, Command, World, StandardMaterial, Mesh, Assets, error,
-        BuildChildren, Mesh3d, MeshMaterial3d, Transform, Visibility,
+        BuildChildren, Mesh3d, MeshMaterial3d, Transform, Visibility, Query, Parent, Ref,
+        ResMut, DetectChanges, DetectChangesMut, Camera, GlobalTransform, Node, PositionType,
+        Res, TargetCamera, Text, TextFont, Val, Vec2, With, Without, Handle, Quat, Vec3,
+        ChildBuild, Resource,
     },
+    utils::HashMap,
     render::mesh::primitives::{Meshable, ConeMeshBuilder, MeshBuilder},
     math::{
         primitives::{Cone, Rectangle},
     }
 };
 
-use crate::{Pen, Stroke};
+use std::f32::consts::PI;
 
-mod shapes;
+use crate::{
+    MainCamera, Pen, PenInstruction, PenState, Playback, Schedule, Stroke, Timeline,
+};
+
+pub(crate) mod shapes;
 use shapes::*;
 
 #[derive(Debug, Clone)]
 pub struct Crab {
     pub name: String,
-    pub show_arrow: bool,
+    pub avatar: Avatar,
+    /// Float the name of the crab next to it. Crabs without a name never get
+    /// a label. Use [`crate::Sketch::show_labels`] to hide every label at once.
+    pub show_label: bool,
 }
 
 impl Default for Crab {
     fn default() -> Self {
         Crab {
             name: String::new(),
-            show_arrow: true,
+            avatar: Avatar::default(),
+            show_label: true,
         }
     }
 }
 
+/// What the crab of a pen looks like. Every avatar takes on the color of its
+/// pen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Avatar {
+    /// An arrow that is shaped like the strokes of the pen.
+    #[default]
+    Arrow,
+    /// A crab with a shell, legs, and claws.
+    Crab,
+    /// Nothing but the strokes themselves.
+    None,
+}
+
 #[derive(Debug, Component)]
 pub struct CrabName(pub String);
 
@@ -66,26 +91,331 @@ impl Command for AddCrab {
             Visibility::Inherited,
         ));
 
-        if self.crab.show_arrow {
-            let mesh = match pen.stroke {
-                Stroke::Volume(diameter) => {
-                    make_cylinder_arrow_mesh(diameter/2.0)
+        // Headless sketches have nowhere to put meshes and no need for them.
+        let has_assets = world.contains_resource::<Assets<Mesh>>();
+        if has_assets {
+            let material = StandardMaterial::from_color(pen.color);
+            let avatar = match self.crab.avatar {
+                Avatar::Arrow => {
+                    let mesh = make_arrow_mesh(pen.stroke);
+                    let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+                    let material = world.resource_mut::<Assets<StandardMaterial>>().add(material);
+                    Some(world.spawn((
+                        CrabArrow { stroke: pen.stroke },
+                        CrabPaint { pen: self.pen },
+                        Mesh3d(mesh),
+                        MeshMaterial3d(material),
+                    )).id())
+                }
+                Avatar::Crab => {
+                    let material = world.resource_mut::<Assets<StandardMaterial>>().add(material);
+                    Some(spawn_crab_body(world, self.pen, material))
                 }
+                Avatar::None => None,
             };
 
-            let mesh_handle = world.resource_mut::<Assets<Mesh>>().add(mesh);
-            let material_handle = world.resource_mut::<Assets<StandardMaterial>>().add(
-                StandardMaterial::from_color(pen.color)
-            );
-
-            let crab = world.spawn((
-                Mesh3d(mesh_handle),
-                MeshMaterial3d(material_handle),
-            )).id();
+            if let Some(avatar) = avatar {
+                world.entity_mut(self.pen).add_child(avatar);
+            }
+        }
 
-            world.entity_mut(self.pen).add_child(crab);
+        let main_camera = world.get_resource::<MainCamera>().map(|c| c.entity);
+        if let Some(main_camera) = main_camera.filter(|_| self.crab.show_label) {
+            if !self.crab.name.is_empty() {
+                // The label stays hidden until it has been placed on screen.
+                world.spawn((
+                    CrabLabel { pen: self.pen },
+                    Text::new(self.crab.name.clone()),
+                    TextFont {
+                        font_size: LABEL_FONT_SIZE,
+                        ..Default::default()
+                    },
+                    Node {
+                        position_type: PositionType::Absolute,
+                        ..Default::default()
+                    },
+                    Visibility::Hidden,
+                    TargetCamera(main_camera),
+                ));
+            }
         }
 
         world.entity_mut(self.pen).insert(CrabName(self.crab.name));
     }
 }
+
+const LABEL_FONT_SIZE: f32 = 14.0;
+/// Screen distance in logical pixels between a crab and its label.
+const LABEL_OFFSET: Vec2 = Vec2::new(10.0, -24.0);
+
+/// A screen-space text label that tracks the pen of a crab.
+#[derive(Debug, Component)]
+pub(crate) struct CrabLabel {
+    pen: Entity,
+}
+
+/// Whether the labels of the crabs are shown in the sketch as a whole.
+#[derive(Debug, Resource)]
+pub(crate) struct CrabLabels {
+    pub(crate) visible: bool,
+}
+
+impl Default for CrabLabels {
+    fn default() -> Self {
+        CrabLabels { visible: true }
+    }
+}
+
+pub(crate) fn update_crab_labels(
+    mut labels: Query<(&CrabLabel, &mut Node, &mut Visibility)>,
+    pens: Query<&Transform, With<Pen>>,
+    settings: Res<CrabLabels>,
+    main_camera: Res<MainCamera>,
+    cameras: Query<(&Camera, &Transform)>,
+) {
+    if !settings.visible {
+        for (_, _, mut visibility) in &mut labels {
+            visibility.set_if_neq(Visibility::Hidden);
+        }
+        return;
+    }
+
+    let Ok((camera, camera_tf)) = cameras.get(main_camera.entity) else {
+        return;
+    };
+    // Pens and the camera have no parents, so their transforms are already
+    // global, even before transform propagation.
+    let camera_tf = GlobalTransform::from(*camera_tf);
+
+    for (label, mut node, mut visibility) in &mut labels {
+        let screen = pens
+            .get(label.pen)
+            .ok()
+            .and_then(|tf| camera.world_to_viewport(&camera_tf, tf.translation).ok());
+
+        let Some(screen) = screen else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+
+        let screen = screen + LABEL_OFFSET;
+        let (left, top) = (Val::Px(screen.x), Val::Px(screen.y));
+        if node.left != left || node.top != top {
+            node.left = left;
+            node.top = top;
+        }
+        visibility.set_if_neq(Visibility::Inherited);
+    }
+}
+
+#[derive(Debug, Component)]
+pub(crate) struct CrabArrow {
+    stroke: Stroke,
+}
+
+/// Marks the part of an avatar whose material follows the color of its pen.
+/// All other parts of the avatar share the same material.
+#[derive(Debug, Component)]
+pub(crate) struct CrabPaint {
+    pen: Entity,
+}
+
+/// Radius of the shell of a crab avatar.
+const CRAB_SIZE: f32 = 0.02;
+/// How far each half of a pincer is turned away from the other at rest.
+const JAW_OPEN_ANGLE: f32 = 20.0 * PI / 180.0;
+/// Distance the crab covers in one full cycle of its legs.
+const CRAB_STRIDE: f32 = 1.5 * CRAB_SIZE;
+/// How far a leg swings forward and back while walking.
+const LEG_SWING_ANGLE: f32 = 25.0 * PI / 180.0;
+/// How far a leg lifts off the ground while it swings forward.
+const LEG_LIFT_ANGLE: f32 = 20.0 * PI / 180.0;
+/// How long the claws take to snap shut and open again.
+const CLAW_SNAP_SECONDS: f32 = 0.3;
+
+#[derive(Debug, Component)]
+pub(crate) struct CrabLeg {
+    pen: Entity,
+    rest: Transform,
+    /// Offset of this leg within the walking cycle, in radians.
+    phase: f32,
+}
+
+#[derive(Debug, Component)]
+pub(crate) struct CrabJaw {
+    pen: Entity,
+    rest: Transform,
+    /// Which way the jaw opens around its hinge.
+    side: f32,
+}
+
+fn spawn_crab_body(world: &mut World, pen: Entity, material: Handle<StandardMaterial>) -> Entity {
+    let size = CRAB_SIZE;
+    let mut meshes = world.resource_mut::<Assets<Mesh>>();
+    let shell = meshes.add(make_crab_shell_mesh(size));
+    let leg = meshes.add(make_crab_leg_mesh(size));
+    let wrist = Vec3::new(0.55 * size, 0.2 * size, -0.15 * size);
+    let left_arm = meshes.add(make_crab_arm_mesh(wrist, size));
+    let right_arm = meshes.add(make_crab_arm_mesh(wrist * Vec3::new(1.0, -1.0, 1.0), size));
+    let jaw = meshes.add(make_crab_jaw_mesh(size));
+
+    let part = |mesh: &Handle<Mesh>, tf: Transform| {
+        (Mesh3d(mesh.clone()), MeshMaterial3d(material.clone()), tf)
+    };
+
+    world.spawn((Transform::IDENTITY, Visibility::Inherited)).with_children(|body| {
+        body.spawn((
+            CrabPaint { pen },
+            part(&shell, Transform::from_scale(Vec3::new(0.75, 1.0, 1.0))),
+        ));
+
+        for (i, side) in [-1.0_f32, 1.0].into_iter().enumerate() {
+            for (j, x) in [-0.4, 0.0, 0.4].into_iter().enumerate() {
+                // Legs on the right are the legs on the left turned around.
+                let rest = Transform::from_xyz(x * size, side * 0.7 * size, 0.2 * size)
+                    .with_rotation(Quat::from_rotation_z(if side < 0.0 { PI } else { 0.0 }));
+                // Alternate legs move together so the crab always stands on
+                // three of them.
+                let phase = if (i + j) % 2 == 0 { 0.0 } else { PI };
+                body.spawn((CrabLeg { pen, rest, phase }, part(&leg, rest)));
+            }
+
+            let arm = if side < 0.0 { &right_arm } else { &left_arm };
+            let shoulder = Transform::from_xyz(0.5 * size, side * 0.45 * size, 0.3 * size);
+            body.spawn(part(arm, shoulder)).with_children(|claw| {
+                for jaw_side in [-1.0_f32, 1.0] {
+                    let rest = Transform::from_translation(
+                        wrist * Vec3::new(1.0, side, 1.0) + jaw_side * 0.06 * size * Vec3::Y,
+                    )
+                    .with_rotation(Quat::from_rotation_z(jaw_side * JAW_OPEN_ANGLE));
+                    claw.spawn((CrabJaw { pen, rest, side: jaw_side }, part(&jaw, rest)));
+                }
+            });
+        }
+    }).id()
+}
+
+/// How the crab of one pen is moving at the current playback time.
+#[derive(Debug, Default, Clone, Copy)]
+struct CrabMotion {
+    /// Total distance walked so far, counting turns in place as shuffling
+    /// around the edge of the shell.
+    walked: f32,
+    walking: bool,
+    /// Seconds since the pen last started to draw.
+    since_draw_started: Option<f32>,
+}
+
+/// Animate crab avatars from the playback clock so they always match what
+/// their pens are doing, even while scrubbing.
+pub(crate) fn animate_crabs(
+    playback: Res<Playback>,
+    schedule: Res<Schedule>,
+    timeline: Res<Timeline>,
+    pens: Query<(Entity, &Pen)>,
+    mut legs: Query<(&CrabLeg, &mut Transform), Without<CrabJaw>>,
+    mut jaws: Query<(&CrabJaw, &mut Transform), Without<CrabLeg>>,
+) {
+    let now = playback.time;
+    let mut motions: HashMap<Entity, (CrabMotion, bool)> = HashMap::new();
+    for step in schedule.replay(&timeline, now, pens.iter().map(|(e, pen)| (e, *pen))) {
+        let (motion, was_drawing) = motions.entry(step.action.pen).or_default();
+        let PenInstruction::Move { draw, .. } = step.action.instruction else {
+            continue;
+        };
+
+        let time_point = step.time_point;
+        let distance = time_point.path_length + CRAB_SIZE * time_point.turn_angle;
+        motion.walked += step.progress * distance;
+        motion.walking = step.progress < 1.0 && distance > 0.0;
+
+        if draw && !*was_drawing {
+            motion.since_draw_started = Some(now - time_point.start);
+        }
+        *was_drawing = draw;
+    }
+
+    for (leg, mut tf) in &mut legs {
+        let motion = motions.get(&leg.pen).map(|(m, _)| *m).unwrap_or_default();
+        let pose = if motion.walking {
+            let cycle = std::f32::consts::TAU * motion.walked / CRAB_STRIDE + leg.phase;
+            // Each leg lifts while it swings forward and pushes against the
+            // ground while it swings back.
+            let swing = LEG_SWING_ANGLE * cycle.sin();
+            let lift = LEG_LIFT_ANGLE * cycle.cos().max(0.0);
+            leg.rest * Transform::from_rotation(
+                Quat::from_rotation_z(swing) * Quat::from_rotation_x(lift),
+            )
+        } else {
+            leg.rest
+        };
+        tf.set_if_neq(pose);
+    }
+
+    for (jaw, mut tf) in &mut jaws {
+        let motion = motions.get(&jaw.pen).map(|(m, _)| *m).unwrap_or_default();
+        let closed = match motion.since_draw_started {
+            Some(age) if age < CLAW_SNAP_SECONDS => (PI * age / CLAW_SNAP_SECONDS).sin(),
+            _ => 0.0,
+        };
+        let pose = jaw.rest.with_rotation(Quat::from_rotation_z(
+            jaw.side * JAW_OPEN_ANGLE * (1.0 - closed),
+        ));
+        tf.set_if_neq(pose);
+    }
+}
+
+fn make_arrow_mesh(stroke: Stroke) -> Mesh {
+    match stroke {
+        Stroke::Volume(diameter) => {
+            make_cylinder_arrow_mesh(diameter/2.0)
+        }
+        Stroke::Ribbon(width) => {
+            make_flat_arrow_avatar_mesh(width)
+        }
+        Stroke::Pixels(_) => {
+            make_arrow_mesh(Stroke::default())
+        }
+    }
+}
+
+pub(crate) fn update_crab_avatars(
+    paints: Query<(&CrabPaint, &MeshMaterial3d<StandardMaterial>)>,
+    mut arrows: Query<(&mut CrabArrow, &Parent, &Mesh3d)>,
+    states: Query<Ref<PenState>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (paint, material) in &paints {
+        let Ok(state) = states.get(paint.pen) else {
+            continue;
+        };
+
+        if !state.is_changed() {
+            continue;
+        }
+
+        if let Some(material) = materials.get_mut(&material.0) {
+            if material.base_color != state.pen.color {
+                material.base_color = state.pen.color;
+            }
+        }
+    }
+
+    for (mut arrow, parent, mesh) in &mut arrows {
+        let Ok(state) = states.get(parent.get()) else {
+            continue;
+        };
+
+        if !state.is_changed() {
+            continue;
+        }
+
+        if arrow.stroke != state.pen.stroke {
+            arrow.stroke = state.pen.stroke;
+            if let Some(mesh) = meshes.get_mut(&mesh.0) {
+                *mesh = make_arrow_mesh(arrow.stroke);
+            }
+        }
+    }
+}
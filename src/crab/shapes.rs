@@ -60,6 +60,18 @@ impl MeshBuffer {
         Self::default()
     }
 
+    pub(crate) fn positions(&self) -> &[[f32; 3]] {
+        &self.positions
+    }
+
+    pub(crate) fn normals(&self) -> &[[f32; 3]] {
+        &self.normals
+    }
+
+    pub(crate) fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
     pub(crate) fn with_uv(mut self, uv: Vec<[f32; 2]>) -> Self {
         if uv.len() != self.positions.len() {
             panic!(
@@ -570,6 +582,91 @@ pub(crate) fn make_cylinder(height: f32, radius: f32) -> MeshBuffer {
         ))
 }
 
+pub(crate) fn make_sphere(radius: f32, resolution: u32) -> MeshBuffer {
+    let rings = resolution / 2;
+    let circles = (0..=rings).map(|k| {
+        let phi = (k as f32 / rings as f32 - 0.5) * std::f32::consts::PI;
+        Circle {
+            radius: radius * phi.cos(),
+            height: radius * phi.sin(),
+        }
+    });
+
+    let positions: Vec<[f32; 3]> = make_circles(circles, resolution, 0.).collect();
+    let normals: Vec<[f32; 3]> = positions
+        .iter()
+        .map(|p| Vec3::from(*p).normalize_or(Vec3::Z).into())
+        .collect();
+
+    let indices = (0..rings)
+        .flat_map(|k| {
+            let start = k * resolution;
+            let top_start = start + resolution;
+            (0..resolution - 1).flat_map(move |i| {
+                [
+                    start + i,
+                    start + i + 1,
+                    top_start + i + 1,
+                    start + i,
+                    top_start + i + 1,
+                    top_start + i,
+                ]
+            })
+        })
+        .collect();
+
+    MeshBuffer::new(positions, normals, indices)
+}
+
+pub(crate) fn make_tube(start: Vec3, end: Vec3, radius: f32, resolution: u32) -> MeshBuffer {
+    let dp = end - start;
+    let length = dp.length();
+    let bottom = Circle {
+        radius,
+        height: 0.0,
+    };
+    let top = Circle {
+        radius,
+        height: length,
+    };
+
+    make_smooth_wrap([bottom, top], resolution).transform_by(Affine3A::from_rotation_translation(
+        Quat::from_rotation_arc(Vec3::Z, dp / length),
+        start,
+    ))
+}
+
+pub(crate) fn make_ribbon(path: &[Transform], width: f32) -> MeshBuffer {
+    let half_width = width / 2.0;
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    for pose in path {
+        let side = half_width * (pose.rotation * Vec3::Y);
+        let normal: [f32; 3] = (pose.rotation * Vec3::Z).into();
+        positions.push((pose.translation - side).into());
+        positions.push((pose.translation + side).into());
+        normals.extend([normal, normal]);
+    }
+
+    let segments = path.len().saturating_sub(1) as u32;
+    let indices: Vec<u32> = (0..segments)
+        .flat_map(|i| {
+            let right = 2 * i;
+            let left = right + 1;
+            [right, right + 2, left + 2, right, left + 2, left]
+        })
+        .collect();
+
+    let bottom_normals = normals.iter().map(|n| (-Vec3::from(*n)).into()).collect();
+    let bottom_indices = indices
+        .chunks(3)
+        .flat_map(|tri| [tri[0], tri[2], tri[1]])
+        .collect();
+
+    MeshBuffer::new(positions.clone(), normals, indices)
+        .merge_with(MeshBuffer::new(positions, bottom_normals, bottom_indices))
+}
+
 pub(crate) fn make_cylinder_arrow_mesh(r: f32) -> Mesh {
     let t = 8.0*r;
     let tip = [0., 0., t];
@@ -636,6 +733,19 @@ pub(crate) fn flat_arrow_mesh(
     MeshBuffer::new(positions, normals, indices)
 }
 
+pub(crate) fn make_flat_arrow_avatar_mesh(width: f32) -> Mesh {
+    let r = width / 2.0;
+    let top = flat_arrow_mesh(5.5 * r, width, 2.5 * r, 4.0 * r)
+        .merge_with(make_top_circle(Circle { radius: r, height: 0.0 }, 32));
+
+    // Show the same arrow from underneath so it can be seen after rolling.
+    let bottom = top
+        .clone()
+        .transform_by(Affine3A::from_rotation_x(180_f32.to_radians()));
+
+    top.merge_with(bottom).into()
+}
+
 pub(crate) fn flat_arrow_mesh_between(
     start: Vec3,
     stop: Vec3,
@@ -810,3 +920,81 @@ pub(crate) fn make_flat_mesh_for_aabb(aabb: Aabb) -> MeshBuffer {
     make_flat_rect_mesh(2.0 * aabb.half_extents.x, 2.0 * aabb.half_extents.y)
         .transform_by(Affine3A::from_translation(aabb.center.into()))
 }
+
+/// Shell of the crab avatar with its eye stalks, for a crab whose shell has
+/// the given radius.
+pub(crate) fn make_crab_shell_mesh(size: f32) -> Mesh {
+    let resolution = 32;
+    let rim = Circle {
+        radius: size,
+        height: 0.1 * size,
+    };
+    let shoulder = Circle {
+        radius: 0.9 * size,
+        height: 0.4 * size,
+    };
+
+    let mut shell = make_flat_disk(rim, resolution)
+        .merge_with(make_smooth_wrap([rim, shoulder], resolution))
+        .merge_with(make_cone(shoulder, [0., 0., 0.7 * size], resolution));
+
+    for side in [-1.0, 1.0] {
+        let base = Vec3::new(0.6 * size, side * 0.25 * size, 0.0);
+        let stalk = Circle {
+            radius: 0.06 * size,
+            height: 0.3 * size,
+        };
+        shell = shell
+            .merge_with(
+                make_cone(stalk, [0., 0., 0.75 * size], 8)
+                    .transform_by(Affine3A::from_translation(base)),
+            )
+            .merge_with(
+                make_sphere(0.1 * size, 8)
+                    .transform_by(Affine3A::from_translation(base + 0.75 * size * Vec3::Z)),
+            );
+    }
+
+    shell.into()
+}
+
+/// A bent leg that starts at the origin and reaches outwards along +Y down to
+/// its foot.
+pub(crate) fn make_crab_leg_mesh(size: f32) -> Mesh {
+    let knee = Vec3::new(0.0, 0.6 * size, 0.25 * size);
+    let foot = Vec3::new(0.0, 0.95 * size, -0.25 * size);
+    let width = 0.08 * size;
+    make_diamond_between(Vec3::ZERO, knee, width)
+        .merge_with(make_diamond_between(knee, foot, width))
+        .into()
+}
+
+/// The arm of a claw, reaching from the shell at the origin to the wrist.
+pub(crate) fn make_crab_arm_mesh(wrist: Vec3, size: f32) -> Mesh {
+    make_diamond_between(Vec3::ZERO, wrist, 0.1 * size)
+        .merge_with(make_sphere(0.15 * size, 16).transform_by(Affine3A::from_translation(wrist)))
+        .into()
+}
+
+/// One half of a pincer, pointing forward along +X from its hinge.
+pub(crate) fn make_crab_jaw_mesh(size: f32) -> Mesh {
+    make_cone(
+        Circle {
+            radius: 0.12 * size,
+            height: 0.0,
+        },
+        [0., 0., 0.55 * size],
+        16,
+    )
+    .transform_by(Affine3A::from_rotation_y(90_f32.to_radians()))
+    .into()
+}
+
+fn make_diamond_between(start: Vec3, end: Vec3, width: f32) -> MeshBuffer {
+    let dp = end - start;
+    let length = dp.length();
+    make_diamond(length / 2.0, width).transform_by(Affine3A::from_rotation_translation(
+        Quat::from_rotation_arc(Vec3::Z, dp / length),
+        (start + end) / 2.0,
+    ))
+}
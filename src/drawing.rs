@@ -0,0 +1,248 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use bevy::prelude::{Color, Entity, Mesh, Transform, Vec3, World};
+
+use crate::{
+    crab::shapes::MeshBuffer, make_stroke_mesh, CrabName, Pen, PenHandle, PenInstruction, PenState,
+    Schedule, Stroke, Timeline,
+};
+
+/// Everything that a sketch has drawn up to some moment of its playback.
+#[derive(Debug, Clone, Default)]
+pub struct Drawing {
+    pub pens: Vec<PenDrawing>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PenDrawing {
+    pub handle: PenHandle,
+    pub name: String,
+    pub pose: Transform,
+    pub pen: Pen,
+    pub down: bool,
+    pub strokes: Vec<DrawnStroke>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DrawnStroke {
+    pub color: Color,
+    pub stroke: Stroke,
+    pub path: Vec<Transform>,
+    /// Geometry of the stroke. Screen-space strokes have no geometry of their
+    /// own, so this will be [`None`] for [`Stroke::Pixels`].
+    pub mesh: Option<Mesh>,
+    /// The geometry that [`Self::mesh`] was made from, kept for exporters.
+    pub(crate) geometry: Option<MeshBuffer>,
+}
+
+impl Drawing {
+    pub fn pen(&self, handle: PenHandle) -> Option<&PenDrawing> {
+        self.pens.iter().find(|p| p.handle == handle)
+    }
+
+    pub(crate) fn at(world: &mut World, time: f32) -> Drawing {
+        let mut pens: Vec<PenDrawing> = world
+            .query::<(Entity, &Pen, Option<&CrabName>)>()
+            .iter(world)
+            .map(|(entity, pen, name)| PenDrawing {
+                handle: PenHandle(entity),
+                name: name.map(|n| n.0.clone()).unwrap_or_default(),
+                pose: Transform::IDENTITY,
+                pen: *pen,
+                down: PenState::new(*pen).down,
+                strokes: Vec::new(),
+            })
+            .collect();
+        pens.sort_by_key(|p| p.handle.0);
+
+        let (Some(schedule), Some(timeline)) = (
+            world.get_resource::<Schedule>(),
+            world.get_resource::<Timeline>(),
+        ) else {
+            return Drawing { pens };
+        };
+
+        let initial_pens: Vec<(Entity, Pen)> = pens.iter().map(|p| (p.handle.0, p.pen)).collect();
+        for step in schedule.replay(timeline, time, initial_pens) {
+            let Some(drawing) = pens.iter_mut().find(|p| p.handle.0 == step.action.pen) else {
+                continue;
+            };
+
+            drawing.pen = step.state.pen;
+            drawing.down = step.state.down;
+            let PenInstruction::Move { movement, draw } = step.action.instruction else {
+                continue;
+            };
+
+            let initial_pose = &step.time_point.initial_pose;
+            drawing.pose = movement.apply_from(initial_pose, step.progress);
+            if draw && step.progress > 0.0 {
+                let stroke = drawing.pen.stroke;
+                let path = movement.trace(initial_pose, step.progress);
+                let geometry = match stroke {
+                    Stroke::Pixels(_) => None,
+                    stroke => Some(make_stroke_mesh(stroke, &path)),
+                };
+                drawing.strokes.push(DrawnStroke {
+                    color: drawing.pen.color,
+                    stroke,
+                    path,
+                    mesh: geometry.clone().map(Into::into),
+                    geometry,
+                });
+            }
+        }
+
+        Drawing { pens }
+    }
+}
+
+impl DrawnStroke {
+    pub fn points(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.path.iter().map(|tf| tf.translation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{prelude::Mesh, time::TimeUpdateStrategy};
+
+    use super::*;
+    use crate::{Playback, Sketch};
+
+    const RED: Color = Color::srgb(1.0, 0.0, 0.0);
+    const RADIUS: f32 = 0.005;
+
+    /// A unit square, then a gap with the pen lifted, then a quarter circle
+    /// in red. Returns the pen along with the index of the gap action.
+    fn sketch() -> (Sketch, PenHandle, usize) {
+        let mut sketch = Sketch::headless();
+        let mut pen = sketch.spawn_pen(Pen {
+            color: Color::WHITE,
+            stroke: Stroke::Volume(2.0 * RADIUS),
+            ..Default::default()
+        });
+        for _ in 0..4 {
+            pen.draw_forward(1.0);
+            pen.turn_left(90.0);
+        }
+        pen.pen_up();
+        pen.draw_forward(0.5);
+        pen.pen_down();
+        pen.set_color(RED);
+        pen.draw_arc(0.5, 90.0);
+        let handle = pen.handle();
+        (sketch, handle, 9)
+    }
+
+    fn positions(stroke: &DrawnStroke) -> Vec<Vec3> {
+        let mesh = stroke.mesh.as_ref().unwrap();
+        let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
+        positions.as_float3().unwrap().iter().map(|p| Vec3::from(*p)).collect()
+    }
+
+    fn distance_to_segment(p: Vec3, a: Vec3, b: Vec3) -> f32 {
+        let t = ((p - a).dot(b - a) / (b - a).length_squared()).clamp(0.0, 1.0);
+        p.distance(a + t * (b - a))
+    }
+
+    #[test]
+    fn finished_drawing_has_every_stroke_and_pose() {
+        let (mut sketch, handle, _) = sketch();
+        let drawing = sketch.evaluate();
+        let pen = drawing.pen(handle).unwrap();
+
+        // The arc ends a quarter turn later, heading up the page.
+        assert!(pen.pose.translation.distance(Vec3::new(1.0, 0.5, 0.0)) < 1e-4);
+        assert!((pen.pose.rotation * Vec3::X).distance(Vec3::Y) < 1e-4);
+        assert!(pen.down);
+        assert_eq!(pen.pen.color, RED);
+
+        // Lifting the pen leaves no stroke behind for the gap.
+        assert_eq!(pen.strokes.len(), 5);
+        let corners = [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y, Vec3::ZERO];
+        for (stroke, side) in pen.strokes.iter().zip(corners.windows(2)) {
+            assert_eq!(stroke.color, Color::WHITE);
+            let points: Vec<Vec3> = stroke.points().collect();
+            assert!(points.first().unwrap().distance(side[0]) < 1e-4);
+            assert!(points.last().unwrap().distance(side[1]) < 1e-4);
+            for p in positions(stroke) {
+                assert!(distance_to_segment(p, side[0], side[1]) < RADIUS + 1e-4);
+            }
+        }
+
+        let arc = &pen.strokes[4];
+        assert_eq!(arc.color, RED);
+        let center = Vec3::new(0.5, 0.5, 0.0);
+        for p in arc.points() {
+            assert!((p.distance(center) - 0.5).abs() < 1e-4);
+        }
+        for p in positions(arc) {
+            assert!((p.distance(center) - 0.5).abs() < RADIUS + 1e-4);
+        }
+    }
+
+    #[test]
+    fn drawing_part_way_through_stops_at_the_pen() {
+        let (mut sketch, handle, gap) = sketch();
+        sketch.app.world_mut().flush();
+        let time_point = sketch.app.world().resource::<Timeline>().time_points[gap];
+
+        let time = (time_point.start + time_point.finish) / 2.0;
+        let drawing = sketch.evaluate_at(time);
+        let pen = drawing.pen(handle).unwrap();
+        assert!(pen.pose.translation.distance(Vec3::new(0.25, 0.0, 0.0)) < 1e-4);
+        assert!(!pen.down);
+        assert_eq!(pen.pen.color, Color::WHITE);
+        assert_eq!(pen.strokes.len(), 4);
+
+        // Halfway along the first side there is half a side drawn.
+        let first = sketch.app.world().resource::<Timeline>().time_points[0];
+        let drawing = sketch.evaluate_at(first.finish / 2.0);
+        let pen = drawing.pen(handle).unwrap();
+        assert_eq!(pen.strokes.len(), 1);
+        let end = pen.strokes[0].points().last().unwrap();
+        assert!(end.distance(Vec3::new(0.5, 0.0, 0.0)) < 1e-4);
+        assert!(pen.pose.translation.distance(end) < 1e-4);
+    }
+
+    #[test]
+    fn playback_agrees_with_the_evaluated_drawing() {
+        let (mut sketch, handle, gap) = sketch();
+        sketch.app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+        sketch.app.world_mut().flush();
+        let timeline = sketch.app.world().resource::<Timeline>().clone();
+
+        let gap_time = (timeline.time_points[gap].start + timeline.time_points[gap].finish) / 2.0;
+        for time in [gap_time, timeline.duration()] {
+            sketch.app.world_mut().resource_mut::<Playback>().time = time;
+            sketch.app.update();
+
+            let drawing = sketch.evaluate_at(time);
+            let expected = drawing.pen(handle).unwrap();
+            let world = sketch.app.world();
+            let state = world.get::<PenState>(handle.0).unwrap();
+            assert_eq!(state.down, expected.down);
+            assert_eq!(state.pen, expected.pen);
+            let pose = world.get::<Transform>(handle.0).unwrap();
+            assert!(pose.translation.distance(expected.pose.translation) < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::{fmt::Write, path::Path};
+
+use bevy::prelude::{warn, Color, Entity, Transform, Vec2, Vec3, World};
+
+use crate::{
+    Movement, Pen, PenHandle, PenInstruction, Schedule, Sketch, Timeline,
+};
+
+const GCODE_TOLERANCE: f32 = 1e-3;
+
+/// How a sketch gets laid out on the bed of a pen plotter. All distances are
+/// in millimetres and all feed rates are in millimetres per minute.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcodeSettings {
+    /// How many millimetres one sketch unit becomes.
+    pub scale: f32,
+    /// Where the origin of the sketch lands on the bed.
+    pub origin: Vec2,
+    pub bed_size: Vec2,
+    pub draw_feed_rate: f32,
+    pub travel_feed_rate: f32,
+    pub pen_up: String,
+    pub pen_down: String,
+    /// Pause the plotter so the pen can be swapped whenever the color of the
+    /// strokes changes.
+    pub pause_on_color_change: bool,
+}
+
+impl Default for GcodeSettings {
+    fn default() -> Self {
+        let bed_size = Vec2::new(300.0, 218.0);
+        Self {
+            scale: 100.0,
+            origin: bed_size / 2.0,
+            bed_size,
+            draw_feed_rate: 1000.0,
+            travel_feed_rate: 3000.0,
+            pen_up: "G0 Z5".to_owned(),
+            pen_down: "G1 Z0".to_owned(),
+            pause_on_color_change: true,
+        }
+    }
+}
+
+/// A stroke that reaches past the edge of the plotter bed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutOfBounds {
+    pub pen: PenHandle,
+    /// The first point of the stroke that is off the bed, in millimetres.
+    pub point: Vec2,
+}
+
+impl std::fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "stroke leaves the plotter bed at ({:.1}, {:.1}) mm",
+            self.point.x, self.point.y,
+        )
+    }
+}
+
+impl Sketch {
+    /// Save the strokes of the sketch as G-code for a pen plotter, looking
+    /// down on the XY plane. Every stroke that does not fit on the bed is
+    /// returned as a warning, but still gets written.
+    pub fn export_gcode(
+        &mut self,
+        path: impl AsRef<Path>,
+        settings: &GcodeSettings,
+    ) -> std::io::Result<Vec<OutOfBounds>> {
+        let (gcode, warnings) = self.to_gcode(settings);
+        std::fs::write(path, gcode)?;
+        Ok(warnings)
+    }
+
+    pub fn to_gcode(&mut self, settings: &GcodeSettings) -> (String, Vec<OutOfBounds>) {
+        let world = self.app.world_mut();
+        world.flush();
+        generate_gcode(world, settings)
+    }
+}
+
+fn generate_gcode(world: &mut World, settings: &GcodeSettings) -> (String, Vec<OutOfBounds>) {
+    let pens: Vec<(Entity, Pen)> = world
+        .query::<(Entity, &Pen)>()
+        .iter(world)
+        .map(|(entity, pen)| (entity, *pen))
+        .collect();
+
+    let mut writer = GcodeWriter::new(settings);
+    let (Some(schedule), Some(timeline)) = (
+        world.get_resource::<Schedule>(),
+        world.get_resource::<Timeline>(),
+    ) else {
+        return writer.finish();
+    };
+
+    // Pens may draw at the same time during playback, but a plotter only has
+    // one head, so plot everything from one pen before moving on to the next.
+    let mut pen_order: Vec<Entity> = Vec::new();
+    for action in &schedule.actions {
+        if !pen_order.contains(&action.pen) {
+            pen_order.push(action.pen);
+        }
+    }
+    let mut steps: Vec<_> = schedule.replay(timeline, f32::INFINITY, pens).collect();
+    steps.sort_by_key(|step| pen_order.iter().position(|pen| *pen == step.action.pen));
+
+    for step in steps {
+        if let PenInstruction::Move { movement, draw: true } = step.action.instruction {
+            writer.draw(
+                PenHandle(step.action.pen),
+                step.state.pen.color,
+                movement,
+                &step.time_point.initial_pose,
+            );
+        }
+    }
+
+    writer.finish()
+}
+
+struct GcodeWriter<'a> {
+    settings: &'a GcodeSettings,
+    code: String,
+    position: Option<Vec2>,
+    down: bool,
+    color: Option<Color>,
+    warnings: Vec<OutOfBounds>,
+}
+
+impl<'a> GcodeWriter<'a> {
+    fn new(settings: &'a GcodeSettings) -> Self {
+        let mut code = String::from("; crab-edu sketch\nG21 ; millimetres\nG90 ; absolute positions\n");
+        let _ = writeln!(code, "{}", settings.pen_up);
+        Self {
+            settings,
+            code,
+            position: None,
+            down: false,
+            color: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn to_bed(&self, p: Vec3) -> Vec2 {
+        self.settings.origin + self.settings.scale * p.truncate()
+    }
+
+    fn draw(&mut self, pen: PenHandle, color: Color, movement: Movement, initial_pose: &Transform) {
+        let path: Vec<Vec2> = movement
+            .trace(initial_pose, 1.0)
+            .iter()
+            .map(|tf| self.to_bed(tf.translation))
+            .collect();
+        let (Some(start), Some(end)) = (path.first().copied(), path.last().copied()) else {
+            return;
+        };
+
+        // Strokes that only change the height of the pen leave nothing on the
+        // paper.
+        if path.iter().all(|p| p.distance(start) < GCODE_TOLERANCE) {
+            return;
+        }
+
+        if let Some(point) = path.iter().copied().find(|p| !self.on_bed(*p)) {
+            let warning = OutOfBounds { pen, point };
+            warn!("{warning}");
+            let _ = writeln!(self.code, "; WARNING: {warning}");
+            self.warnings.push(warning);
+        }
+
+        if self.color.is_some_and(|c| c != color) && self.settings.pause_on_color_change {
+            self.lift();
+            let _ = writeln!(
+                self.code,
+                "M0 ; change to the {} pen",
+                color.to_srgba().to_hex(),
+            );
+        }
+        self.color = Some(color);
+
+        if self.position.is_none_or(|p| p.distance(start) >= GCODE_TOLERANCE) {
+            self.lift();
+            let _ = writeln!(
+                self.code,
+                "G0 X{:.3} Y{:.3} F{}",
+                start.x, start.y, self.settings.travel_feed_rate,
+            );
+        }
+        self.lower();
+
+        match self.arc_plane(movement, initial_pose) {
+            Some((center, counter_clockwise)) => {
+                let Movement::Arc { sweep, .. } = movement else {
+                    return;
+                };
+
+                // Split the arc into quarter turns or less so that controllers
+                // never need to guess which way a full circle should go.
+                let pieces = (sweep.abs() / std::f32::consts::FRAC_PI_2).ceil().max(1.0) as u32;
+                let command = if counter_clockwise { "G3" } else { "G2" };
+                let mut from = start;
+                for k in 1..=pieces {
+                    let to = if k == pieces {
+                        end
+                    } else {
+                        let progress = k as f32 / pieces as f32;
+                        self.to_bed(movement.apply_from(initial_pose, progress).translation)
+                    };
+                    let offset = center - from;
+                    let _ = writeln!(
+                        self.code,
+                        "{command} X{:.3} Y{:.3} I{:.3} J{:.3} F{}",
+                        to.x, to.y, offset.x, offset.y, self.settings.draw_feed_rate,
+                    );
+                    from = to;
+                }
+            }
+            None => {
+                for p in &path[1..] {
+                    let _ = writeln!(
+                        self.code,
+                        "G1 X{:.3} Y{:.3} F{}",
+                        p.x, p.y, self.settings.draw_feed_rate,
+                    );
+                }
+            }
+        }
+
+        self.position = Some(end);
+    }
+
+    /// Arcs can only be sent as G2/G3 when they lie flat on the bed. Returns
+    /// the center of the arc on the bed and whether it turns counter-clockwise.
+    fn arc_plane(&self, movement: Movement, initial_pose: &Transform) -> Option<(Vec2, bool)> {
+        let Movement::Arc { radius, sweep } = movement else {
+            return None;
+        };
+
+        let normal = (initial_pose.rotation * Vec3::Z).z;
+        if normal.abs() < 1.0 - GCODE_TOLERANCE || self.settings.scale <= 0.0 {
+            return None;
+        }
+
+        let side = if sweep < 0.0 { -radius } else { radius };
+        let center = initial_pose.translation + initial_pose.rotation * (side * Vec3::Y);
+        Some((self.to_bed(center), (sweep > 0.0) == (normal > 0.0)))
+    }
+
+    fn on_bed(&self, p: Vec2) -> bool {
+        p.cmpge(Vec2::ZERO).all() && p.cmple(self.settings.bed_size).all()
+    }
+
+    fn lift(&mut self) {
+        if self.down {
+            let _ = writeln!(self.code, "{}", self.settings.pen_up);
+            self.down = false;
+        }
+    }
+
+    fn lower(&mut self) {
+        if !self.down {
+            let _ = writeln!(self.code, "{}", self.settings.pen_down);
+            self.down = true;
+        }
+    }
+
+    fn finish(mut self) -> (String, Vec<OutOfBounds>) {
+        self.lift();
+        (self.code, self.warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PenCommands;
+
+    fn gcode(draw: impl FnOnce(&mut PenCommands)) -> (String, Vec<OutOfBounds>) {
+        let mut sketch = Sketch::headless();
+        draw(&mut sketch.spawn_pen(Color::WHITE));
+        sketch.to_gcode(&GcodeSettings::default())
+    }
+
+    fn commands<'a>(code: &'a str, command: &str) -> Vec<&'a str> {
+        code.lines().filter(|line| line.split(' ').next() == Some(command)).collect()
+    }
+
+    #[test]
+    fn left_arcs_are_counter_clockwise_and_right_arcs_clockwise() {
+        // The bed origin is at (150, 109) and one unit is 100 mm.
+        let (left, _) = gcode(|pen| pen.draw_arc(0.5, 90.0));
+        assert_eq!(commands(&left, "G3"), ["G3 X200.000 Y159.000 I0.000 J50.000 F1000"]);
+        assert!(commands(&left, "G2").is_empty());
+
+        let (right, _) = gcode(|pen| pen.draw_arc(0.5, -90.0));
+        assert_eq!(commands(&right, "G2"), ["G2 X200.000 Y59.000 I0.000 J-50.000 F1000"]);
+        assert!(commands(&right, "G3").is_empty());
+    }
+
+    #[test]
+    fn arcs_of_an_upside_down_pen_turn_the_other_way() {
+        // Rolled over, the left of the pen is towards -Y on the bed.
+        let (left, _) = gcode(|pen| {
+            pen.roll_left(180.0);
+            pen.draw_arc(0.5, 90.0);
+        });
+        assert_eq!(commands(&left, "G2"), ["G2 X200.000 Y59.000 I0.000 J-50.000 F1000"]);
+        assert!(commands(&left, "G3").is_empty());
+
+        let (right, _) = gcode(|pen| {
+            pen.roll_left(180.0);
+            pen.draw_arc(0.5, -90.0);
+        });
+        assert_eq!(commands(&right, "G3"), ["G3 X200.000 Y159.000 I0.000 J50.000 F1000"]);
+        assert!(commands(&right, "G2").is_empty());
+    }
+
+    #[test]
+    fn arcs_with_the_pen_lifted_only_move_the_head() {
+        let (code, _) = gcode(|pen| {
+            pen.pen_up();
+            pen.draw_arc(0.5, 90.0);
+            pen.pen_down();
+            pen.draw_forward(0.1);
+        });
+        assert!(commands(&code, "G2").is_empty());
+        assert!(commands(&code, "G3").is_empty());
+        assert_eq!(commands(&code, "G0")[1..], ["G0 X200.000 Y159.000 F3000", "G0 Z5"]);
+        assert_eq!(commands(&code, "G1"), ["G1 Z0", "G1 X200.000 Y169.000 F1000"]);
+    }
+
+    #[test]
+    fn circles_are_split_into_quarter_turns() {
+        let (code, _) = gcode(|pen| pen.draw_circle(0.5));
+        assert_eq!(
+            commands(&code, "G3"),
+            [
+                "G3 X200.000 Y159.000 I0.000 J50.000 F1000",
+                "G3 X150.000 Y209.000 I-50.000 J0.000 F1000",
+                "G3 X100.000 Y159.000 I0.000 J-50.000 F1000",
+                "G3 X150.000 Y109.000 I50.000 J0.000 F1000",
+            ],
+        );
+    }
+
+    #[test]
+    fn strokes_off_the_bed_are_warned_about_but_still_written() {
+        let (code, warnings) = gcode(|pen| pen.draw_forward(1.0));
+        assert!(warnings.is_empty());
+        assert!(!code.contains("WARNING"));
+
+        let (code, warnings) = gcode(|pen| {
+            pen.draw_forward(1.0);
+            pen.draw_forward(1.0);
+        });
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].point.x > 300.0);
+        assert_eq!(code.matches("; WARNING").count(), 1);
+        assert_eq!(commands(&code, "G1").last(), Some(&"G1 X350.000 Y109.000 F1000"));
+    }
+}
@@ -0,0 +1,299 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::{fmt::Write, path::Path};
+
+use bevy::prelude::{Color, ColorToComponents, Vec3};
+
+use crate::{crab::shapes::MeshBuffer, Drawing, Sketch};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+impl Sketch {
+    /// Save the 3D geometry of every stroke into a binary glTF file, with one
+    /// node for each pen.
+    pub fn export_glb(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.evaluate().to_glb())
+    }
+}
+
+impl Drawing {
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut gltf = GltfBuilder::default();
+        for (i, pen) in self.pens.iter().enumerate() {
+            // Merge strokes that share a color so that each pen only needs one
+            // primitive per material.
+            let mut groups: Vec<(Color, MeshBuffer)> = Vec::new();
+            for stroke in &pen.strokes {
+                let Some(geometry) = stroke.geometry.clone() else {
+                    continue;
+                };
+
+                match groups.iter_mut().find(|(color, _)| *color == stroke.color) {
+                    Some((_, buffer)) => {
+                        *buffer = std::mem::take(buffer).merge_with(geometry);
+                    }
+                    None => groups.push((stroke.color, geometry)),
+                }
+            }
+
+            let name = if pen.name.is_empty() {
+                format!("pen {i}")
+            } else {
+                pen.name.clone()
+            };
+            gltf.add_node(&name, groups);
+        }
+
+        gltf.finish()
+    }
+}
+
+#[derive(Default)]
+struct GltfBuilder {
+    nodes: Vec<String>,
+    meshes: Vec<String>,
+    materials: Vec<String>,
+    accessors: Vec<String>,
+    buffer_views: Vec<String>,
+    bin: Vec<u8>,
+}
+
+impl GltfBuilder {
+    fn add_node(&mut self, name: &str, groups: Vec<(Color, MeshBuffer)>) {
+        let name = json_string(name);
+        if groups.is_empty() {
+            self.nodes.push(format!(r#"{{"name":{name}}}"#));
+            return;
+        }
+
+        let mut primitives = Vec::new();
+        for (color, buffer) in groups {
+            let material = self.add_material(color);
+            let positions = self.add_vec3_accessor(buffer.positions(), true);
+            let normals = self.add_vec3_accessor(buffer.normals(), false);
+            let indices = self.add_index_accessor(buffer.indices());
+            primitives.push(format!(
+                r#"{{"attributes":{{"POSITION":{positions},"NORMAL":{normals}}},"indices":{indices},"material":{material}}}"#
+            ));
+        }
+
+        let mesh = self.meshes.len();
+        self.meshes.push(format!(
+            r#"{{"name":{name},"primitives":[{}]}}"#,
+            primitives.join(","),
+        ));
+        self.nodes
+            .push(format!(r#"{{"name":{name},"mesh":{mesh}}}"#));
+    }
+
+    fn add_material(&mut self, color: Color) -> usize {
+        let [r, g, b, a] = color.to_linear().to_f32_array();
+        let alpha_mode = if a < 1.0 {
+            r#","alphaMode":"BLEND""#
+        } else {
+            ""
+        };
+        self.materials.push(format!(
+            r#"{{"pbrMetallicRoughness":{{"baseColorFactor":[{r},{g},{b},{a}],"metallicFactor":0,"roughnessFactor":0.5}},"doubleSided":true{alpha_mode}}}"#
+        ));
+        self.materials.len() - 1
+    }
+
+    fn add_vec3_accessor(&mut self, values: &[[f32; 3]], bounds: bool) -> usize {
+        let view = self.add_buffer_view(
+            values.iter().flatten().flat_map(|v| v.to_le_bytes()),
+            ARRAY_BUFFER,
+        );
+
+        // glTF requires the bounds of every position accessor.
+        let bounds = if bounds {
+            let (min, max) = values
+                .iter()
+                .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), v| {
+                    (min.min((*v).into()), max.max((*v).into()))
+                });
+            format!(
+                r#","min":[{},{},{}],"max":[{},{},{}]"#,
+                min.x, min.y, min.z, max.x, max.y, max.z,
+            )
+        } else {
+            String::new()
+        };
+
+        self.accessors.push(format!(
+            r#"{{"bufferView":{view},"componentType":{FLOAT},"count":{},"type":"VEC3"{bounds}}}"#,
+            values.len(),
+        ));
+        self.accessors.len() - 1
+    }
+
+    fn add_index_accessor(&mut self, indices: &[u32]) -> usize {
+        let view = self.add_buffer_view(
+            indices.iter().flat_map(|i| i.to_le_bytes()),
+            ELEMENT_ARRAY_BUFFER,
+        );
+        self.accessors.push(format!(
+            r#"{{"bufferView":{view},"componentType":{UNSIGNED_INT},"count":{},"type":"SCALAR"}}"#,
+            indices.len(),
+        ));
+        self.accessors.len() - 1
+    }
+
+    fn add_buffer_view(&mut self, bytes: impl Iterator<Item = u8>, target: u32) -> usize {
+        let offset = self.bin.len();
+        self.bin.extend(bytes);
+        self.buffer_views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{},"target":{target}}}"#,
+            self.bin.len() - offset,
+        ));
+        self.buffer_views.len() - 1
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            r#"{{"asset":{{"version":"2.0","generator":"crab-edu"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}]"#,
+            (0..self.nodes.len())
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            self.nodes.join(","),
+        );
+        for (key, items) in [
+            ("meshes", &self.meshes),
+            ("materials", &self.materials),
+            ("accessors", &self.accessors),
+            ("bufferViews", &self.buffer_views),
+        ] {
+            if !items.is_empty() {
+                let _ = write!(json, r#","{key}":[{}]"#, items.join(","));
+            }
+        }
+        if !self.bin.is_empty() {
+            let _ = write!(json, r#","buffers":[{{"byteLength":{}}}]"#, self.bin.len());
+        }
+        json.push('}');
+
+        // Both chunks must be padded to a multiple of four bytes.
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = self.bin;
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut total = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            total += 8 + bin.len();
+        }
+
+        let mut glb = Vec::with_capacity(total);
+        for word in [
+            GLB_MAGIC,
+            GLB_VERSION,
+            total as u32,
+            json.len() as u32,
+            CHUNK_JSON,
+        ] {
+            glb.extend(word.to_le_bytes());
+        }
+        glb.extend(json);
+        if !bin.is_empty() {
+            for word in [bin.len() as u32, CHUNK_BIN] {
+                glb.extend(word.to_le_bytes());
+            }
+            glb.extend(bin);
+        }
+
+        glb
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pen, Stroke};
+
+    fn word(glb: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(glb[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn glb_chunks_are_laid_out_and_aligned() {
+        let mut sketch = Sketch::headless();
+        let mut pen = sketch.spawn_pen(Color::WHITE);
+        pen.draw_forward(0.2);
+        pen.draw_arc(0.2, 90.0);
+        pen.set_color(Color::BLACK);
+        pen.draw_forward(0.2);
+        sketch
+            .spawn_pen(Pen { stroke: Stroke::Pixels(2), ..Color::WHITE.into() })
+            .draw_forward(0.2);
+        let glb = sketch.evaluate().to_glb();
+
+        assert_eq!(word(&glb, 0), GLB_MAGIC);
+        assert_eq!(word(&glb, 4), GLB_VERSION);
+        assert_eq!(word(&glb, 8) as usize, glb.len());
+
+        let json_length = word(&glb, 12) as usize;
+        assert_eq!(word(&glb, 16), CHUNK_JSON);
+        assert_eq!(json_length % 4, 0);
+        let json = std::str::from_utf8(&glb[20..20 + json_length]).unwrap().trim_end();
+        assert!(json.starts_with(r#"{"asset":{"version":"2.0""#));
+        assert!(json.ends_with('}'));
+
+        let bin_header = 20 + json_length;
+        let bin_length = word(&glb, bin_header) as usize;
+        assert_eq!(word(&glb, bin_header + 4), CHUNK_BIN);
+        assert_eq!(bin_length % 4, 0);
+        assert_eq!(bin_header + 8 + bin_length, glb.len());
+        let buffer = r#""buffers":[{"byteLength":"#;
+        let start = json.find(buffer).unwrap() + buffer.len();
+        let end = start + json[start..].find('}').unwrap();
+        let buffer_length: usize = json[start..end].parse().unwrap();
+        assert!(buffer_length <= bin_length && bin_length < buffer_length + 4);
+
+        // The white strokes share a primitive while the pixel pen has no mesh.
+        assert_eq!(json.matches(r#""attributes""#).count(), 2);
+        assert_eq!(json.matches(r#""mesh":"#).count(), 1);
+        assert!(json.contains(r#""nodes":[{"name":"pen 0","mesh":0},{"name":"pen 1"}]"#));
+    }
+}
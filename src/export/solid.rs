@@ -0,0 +1,484 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::{fmt::Write, path::Path};
+
+use bevy::{
+    math::{Affine3A, IVec3, Vec3},
+    utils::HashMap,
+};
+
+use crate::{crab::shapes::MeshBuffer, Drawing, DrawnStroke, Sketch, Stroke};
+
+/// How many grid cells fit across the radius of the thinnest stroke when the
+/// strokes get melted together.
+const SOLID_CELLS_PER_RADIUS: f32 = 2.0;
+
+/// Surface points are kept at least this fraction of a cell edge away from the
+/// grid points so that no triangle collapses to nothing.
+const SOLID_EDGE_MARGIN: f32 = 0.01;
+
+impl Sketch {
+    /// Save the volume strokes of the sketch into a binary STL file for 3D
+    /// printing. One sketch unit becomes one unit in the file.
+    ///
+    /// The strokes of every pen are melted together into one closed surface,
+    /// so wherever strokes cross or touch they become a single solid.
+    pub fn export_stl(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.export_stl_with(path, 1.0)
+    }
+
+    /// Same as [`Self::export_stl`] but each sketch unit becomes `scale` units
+    /// in the file, e.g. use a scale of 100.0 to print 1.0 sketch unit as 100mm.
+    pub fn export_stl_with(&mut self, path: impl AsRef<Path>, scale: f32) -> std::io::Result<()> {
+        std::fs::write(path, self.evaluate().to_stl(scale))
+    }
+
+    /// Save the volume strokes of the sketch into a Wavefront OBJ file with one
+    /// object for each pen, so that each color can be printed in its own
+    /// material. The strokes of each pen are melted together into one closed
+    /// surface. One sketch unit becomes one unit in the file.
+    pub fn export_obj(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.export_obj_with(path, 1.0)
+    }
+
+    /// Same as [`Self::export_obj`] but each sketch unit becomes `scale` units
+    /// in the file.
+    pub fn export_obj_with(&mut self, path: impl AsRef<Path>, scale: f32) -> std::io::Result<()> {
+        std::fs::write(path, self.evaluate().to_obj(scale))
+    }
+}
+
+impl Drawing {
+    /// Binary STL of every volume stroke melted together into one solid.
+    pub fn to_stl(&self, scale: f32) -> Vec<u8> {
+        let solid = make_solid(self.pens.iter().flat_map(|pen| &pen.strokes))
+            .transform_by(Affine3A::from_scale(Vec3::splat(scale)));
+
+        let positions = solid.positions();
+        let triangles = solid.indices().chunks_exact(3);
+
+        let mut stl = Vec::with_capacity(84 + 50 * triangles.len());
+        let mut header = [0_u8; 80];
+        let title = b"crab-edu sketch";
+        header[..title.len()].copy_from_slice(title);
+        stl.extend(header);
+        stl.extend((triangles.len() as u32).to_le_bytes());
+
+        for triangle in triangles {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for v in [normal, a, b, c] {
+                for x in v.to_array() {
+                    stl.extend(x.to_le_bytes());
+                }
+            }
+            stl.extend(0_u16.to_le_bytes());
+        }
+
+        stl
+    }
+
+    /// Wavefront OBJ of every volume stroke with one solid object for each pen.
+    pub fn to_obj(&self, scale: f32) -> String {
+        let mut obj = String::from("# crab-edu sketch\n");
+        // OBJ indices count up from 1 across the whole file.
+        let mut offset = 1;
+        for (i, pen) in self.pens.iter().enumerate() {
+            let solid = make_solid(&pen.strokes);
+            if solid.indices().is_empty() {
+                continue;
+            }
+
+            if pen.name.is_empty() {
+                let _ = writeln!(obj, "o pen_{i}");
+            } else {
+                let _ = writeln!(obj, "o {}", pen.name.replace(char::is_whitespace, "_"));
+            }
+
+            for [x, y, z] in solid.positions() {
+                let _ = writeln!(obj, "v {} {} {}", x * scale, y * scale, z * scale);
+            }
+            for [x, y, z] in solid.normals() {
+                let _ = writeln!(obj, "vn {x} {y} {z}");
+            }
+            for triangle in solid.indices().chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + offset);
+                let _ = writeln!(obj, "f {a}//{a} {b}//{b} {c}//{c}");
+            }
+
+            offset += solid.positions().len() as u32;
+        }
+
+        obj
+    }
+}
+
+/// A volume stroke is the set of points within its radius of its path, which
+/// is the union of one capsule for each piece of the path.
+#[derive(Debug, Clone, Copy)]
+struct Capsule {
+    start: Vec3,
+    end: Vec3,
+    radius: f32,
+}
+
+impl Capsule {
+    /// How far outside of the capsule a point is, which is negative inside.
+    fn distance(&self, p: Vec3) -> f32 {
+        let axis = self.end - self.start;
+        let t = match axis.length_squared() {
+            0.0 => 0.0,
+            length_squared => ((p - self.start).dot(axis) / length_squared).clamp(0.0, 1.0),
+        };
+        p.distance(self.start + t * axis) - self.radius
+    }
+}
+
+fn make_capsules(stroke: &DrawnStroke) -> Vec<Capsule> {
+    let Stroke::Volume(diameter) = stroke.stroke else {
+        return Vec::new();
+    };
+
+    let radius = diameter / 2.0;
+    if radius.is_nan() || radius <= 0.0 {
+        return Vec::new();
+    }
+
+    let points: Vec<Vec3> = stroke.points().collect();
+    match &points[..] {
+        [] => Vec::new(),
+        [p] => vec![Capsule { start: *p, end: *p, radius }],
+        _ => points
+            .windows(2)
+            .map(|w| Capsule { start: w[0], end: w[1], radius })
+            .collect(),
+    }
+}
+
+/// The corners of a grid cell, counting around the bottom and then the top.
+const CELL_CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(1, 1, 1),
+    IVec3::new(0, 1, 1),
+];
+
+/// Six tetrahedra that fill a cell around its diagonal from corner 0 to
+/// corner 6. Every cell is split the same way, so neighboring cells split
+/// their shared faces along the same diagonal and the surface has no cracks.
+const CELL_TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 5, 1, 6],
+    [0, 1, 2, 6],
+    [0, 2, 3, 6],
+    [0, 3, 7, 6],
+    [0, 7, 4, 6],
+    [0, 4, 5, 6],
+];
+
+/// Melt strokes together into one closed surface. The distance to the
+/// nearest stroke is sampled on a grid near the strokes, and the surface
+/// where that distance is zero is traced through tetrahedra of the grid. Each
+/// point of the surface lies on one edge of the grid and is shared by every
+/// triangle that touches it, so the result is watertight with no overlaps.
+fn make_solid<'a>(strokes: impl IntoIterator<Item = &'a DrawnStroke>) -> MeshBuffer {
+    let capsules: Vec<Capsule> = strokes.into_iter().flat_map(make_capsules).collect();
+    let Some(thinnest) = capsules.iter().map(|c| c.radius).min_by(f32::total_cmp) else {
+        return MeshBuffer::empty();
+    };
+    let cell = thinnest / SOLID_CELLS_PER_RADIUS;
+    let point = |key: IVec3| cell * key.as_vec3();
+
+    // Only grid points near a stroke get sampled. Anything further than two
+    // cells outside of every stroke cannot be a corner of a cell that the
+    // surface passes through, so it is simply treated as outside.
+    let mut field: HashMap<IVec3, f32> = HashMap::new();
+    for capsule in &capsules {
+        let margin = Vec3::splat(capsule.radius + 2.0 * cell);
+        let min = ((capsule.start.min(capsule.end) - margin) / cell).floor().as_ivec3();
+        let max = ((capsule.start.max(capsule.end) + margin) / cell).ceil().as_ivec3();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let key = IVec3::new(x, y, z);
+                    let distance = capsule.distance(point(key));
+                    field
+                        .entry(key)
+                        .and_modify(|d| *d = d.min(distance))
+                        .or_insert(distance);
+                }
+            }
+        }
+    }
+    let value = |key: IVec3| field.get(&key).copied().unwrap_or(f32::INFINITY);
+
+    // Each point of the surface sits on an edge of the grid, found by
+    // interpolating the distance between its two ends.
+    let edge = |a: IVec3, b: IVec3| if a.to_array() < b.to_array() { [a, b] } else { [b, a] };
+    let edge_position = |[a, b]: [IVec3; 2]| {
+        let (da, db) = (value(a), value(b));
+        let t = (da / (da - db)).clamp(SOLID_EDGE_MARGIN, 1.0 - SOLID_EDGE_MARGIN);
+        point(a).lerp(point(b), t)
+    };
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut edge_points: HashMap<[IVec3; 2], u32> = HashMap::new();
+
+    for base in field.keys() {
+        let corners = CELL_CORNERS.map(|offset| *base + offset);
+        // Points exactly on the surface count as outside so that every point
+        // is strictly on one side of it.
+        let inside = corners.map(|corner| value(corner) < 0.0);
+        if inside.iter().all(|i| *i) || !inside.iter().any(|i| *i) {
+            continue;
+        }
+
+        for tetrahedron in CELL_TETRAHEDRA {
+            let (ins, outs): (Vec<usize>, Vec<usize>) =
+                tetrahedron.iter().partition(|corner| inside[**corner]);
+            let polygon: Vec<[IVec3; 2]> = match (&ins[..], &outs[..]) {
+                ([i], [o0, o1, o2]) | ([o0, o1, o2], [i]) => {
+                    [o0, o1, o2].map(|o| edge(corners[*i], corners[*o])).to_vec()
+                }
+                ([i0, i1], [o0, o1]) => [(i0, o0), (i0, o1), (i1, o1), (i1, o0)]
+                    .map(|(i, o)| edge(corners[*i], corners[*o]))
+                    .to_vec(),
+                _ => continue,
+            };
+
+            // Face each triangle away from the inside of the solid.
+            let centroid = |group: &[usize]| {
+                group.iter().map(|c| point(corners[*c])).sum::<Vec3>() / group.len() as f32
+            };
+            let outward = centroid(&outs) - centroid(&ins);
+            for k in 1..polygon.len() - 1 {
+                let mut triangle = [polygon[0], polygon[k], polygon[k + 1]];
+                let [a, b, c] = triangle.map(edge_position);
+                let mut normal = (b - a).cross(c - a);
+                if normal.dot(outward) < 0.0 {
+                    triangle.swap(1, 2);
+                    normal = -normal;
+                }
+
+                for edge in triangle {
+                    let index = *edge_points.entry(edge).or_insert_with(|| {
+                        positions.push(edge_position(edge).into());
+                        normals.push(Vec3::ZERO);
+                        (positions.len() - 1) as u32
+                    });
+                    normals[index as usize] += normal;
+                    indices.push(index);
+                }
+            }
+        }
+    }
+
+    let normals = normals.into_iter().map(|n| n.normalize_or_zero().into()).collect();
+    MeshBuffer::new(positions, normals, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use bevy::prelude::Color;
+
+    use super::*;
+    use crate::{Pen, PenCommands};
+
+    const RADIUS: f32 = 0.01;
+
+    fn drawing(draw: impl FnOnce(&mut PenCommands)) -> Drawing {
+        let mut sketch = Sketch::headless();
+        draw(&mut sketch.spawn_pen(Pen {
+            color: Color::WHITE,
+            stroke: Stroke::Volume(2.0 * RADIUS),
+            ..Default::default()
+        }));
+        sketch.evaluate()
+    }
+
+    fn solid(drawing: &Drawing) -> MeshBuffer {
+        make_solid(drawing.pens.iter().flat_map(|pen| &pen.strokes))
+    }
+
+    /// Check that every edge joins exactly two triangles that wind in opposite
+    /// directions, then give the Euler characteristic and the number of
+    /// separate pieces.
+    fn topology(solid: &MeshBuffer) -> (i64, usize) {
+        let triangles: Vec<[u32; 3]> =
+            solid.indices().chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        let mut directed: HashSet<[u32; 2]> = HashSet::new();
+        for [a, b, c] in &triangles {
+            for edge in [[*a, *b], [*b, *c], [*c, *a]] {
+                assert_ne!(edge[0], edge[1]);
+                assert!(directed.insert(edge), "edge {edge:?} is used twice the same way");
+            }
+        }
+        for [a, b] in &directed {
+            assert!(directed.contains(&[*b, *a]), "edge {a}-{b} is open");
+        }
+
+        // Join up the pieces that the triangles connect.
+        let mut root: Vec<u32> = (0..solid.positions().len() as u32).collect();
+        fn find(root: &mut [u32], i: u32) -> u32 {
+            let mut i = i;
+            while root[i as usize] != i {
+                i = root[i as usize];
+            }
+            i
+        }
+        for [a, b] in &directed {
+            let (a, b) = (find(&mut root, *a), find(&mut root, *b));
+            root[a as usize] = b;
+        }
+        let pieces = (0..root.len() as u32)
+            .filter(|i| find(&mut root, *i) == *i)
+            .count();
+
+        let (v, e, f) = (root.len(), directed.len() / 2, triangles.len());
+        (v as i64 - e as i64 + f as i64, pieces)
+    }
+
+    fn volume(solid: &MeshBuffer) -> f32 {
+        let positions = solid.positions();
+        solid
+            .indices()
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[t[i] as usize]));
+                a.dot(b.cross(c)) / 6.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn a_straight_stroke_is_a_capsule() {
+        let solid = solid(&drawing(|pen| pen.draw_forward(0.2)));
+        assert_eq!(topology(&solid), (2, 1));
+
+        // The surface faces outwards, so it encloses a positive volume. Its flat facets cut
+        // slightly inside the round tube, so allow it to come up a little short.
+        let expected = std::f32::consts::PI * RADIUS.powi(2) * (0.2 + 4.0 / 3.0 * RADIUS);
+        assert!((volume(&solid) - expected).abs() < 0.1 * expected);
+    }
+
+    #[test]
+    fn crossing_strokes_melt_into_one_solid() {
+        let drawing = drawing(|pen| {
+            pen.draw_forward(0.2);
+            pen.pen_up();
+            pen.move_to(Vec3::new(0.1, -0.1, 0.0));
+            pen.pen_down();
+            pen.draw_to(Vec3::new(0.1, 0.1, 0.0));
+        });
+        assert_eq!(drawing.pens[0].strokes.len(), 2);
+        assert_eq!(topology(&solid(&drawing)), (2, 1));
+    }
+
+    #[test]
+    fn a_closed_loop_is_a_ring() {
+        let drawing = drawing(|pen| {
+            for _ in 0..4 {
+                pen.draw_forward(0.2);
+                pen.turn_left(90.0);
+            }
+        });
+        assert_eq!(topology(&solid(&drawing)), (0, 1));
+    }
+
+    #[test]
+    fn strokes_apart_stay_apart() {
+        let drawing = drawing(|pen| {
+            pen.draw_forward(0.1);
+            pen.pen_up();
+            pen.draw_forward(0.1);
+            pen.pen_down();
+            pen.draw_forward(0.1);
+        });
+        assert_eq!(topology(&solid(&drawing)), (4, 2));
+    }
+
+    fn three_pens() -> Drawing {
+        let mut sketch = Sketch::headless();
+        for (i, stroke) in [Stroke::Volume(0.02), Stroke::Pixels(2), Stroke::Volume(0.02)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut pen = sketch.spawn_pen(Pen { stroke, ..Color::WHITE.into() });
+            pen.move_to(Vec3::new(0.0, 0.1 * i as f32, 0.0));
+            pen.draw_forward(0.2);
+        }
+        sketch.evaluate()
+    }
+
+    #[test]
+    fn stl_length_matches_its_triangle_count() {
+        let drawing = three_pens();
+        let stl = drawing.to_stl(1.0);
+        let count = u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize;
+        assert_eq!(count, solid(&drawing).indices().len() / 3);
+        assert!(count > 0);
+        assert_eq!(stl.len(), 84 + 50 * count);
+
+        for triangle in stl[84..].chunks_exact(50) {
+            let normal = Vec3::from_array(
+                [0, 4, 8].map(|at| f32::from_le_bytes(triangle[at..at + 4].try_into().unwrap())),
+            );
+            assert!((normal.length() - 1.0).abs() < 1e-3);
+            assert_eq!(triangle[48..], [0, 0]);
+        }
+    }
+
+    #[test]
+    fn obj_faces_point_at_the_vertices_of_their_own_object() {
+        let obj = three_pens().to_obj(1.0);
+        assert!(obj.starts_with("# crab-edu sketch\n"));
+
+        // The pixel pen has no volume so it gets no object.
+        let objects: Vec<_> = obj.lines().filter(|l| l.starts_with("o ")).collect();
+        assert_eq!(objects, ["o pen_0", "o pen_2"]);
+
+        let mut vertices = 0;
+        let mut normals = 0;
+        let mut first = 1;
+        for line in obj.lines() {
+            let mut words = line.split(' ');
+            match words.next() {
+                Some("o") => first = vertices + 1,
+                Some("v") => vertices += 1,
+                Some("vn") => normals += 1,
+                Some("f") => {
+                    for corner in words {
+                        let (v, n) = corner.split_once("//").unwrap();
+                        let (v, n): (usize, usize) = (v.parse().unwrap(), n.parse().unwrap());
+                        assert_eq!(v, n);
+                        assert!((first..=vertices).contains(&v), "{line}");
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(vertices, normals);
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::{fmt::Write, path::Path};
+
+use bevy::prelude::{Alpha, Vec2, Vec3};
+
+use crate::{Drawing, Sketch, Stroke};
+
+/// How the 3D points of a sketch get flattened onto the page of an SVG.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SvgProjection {
+    /// Look down on the XY plane, which is where 2D sketches are drawn.
+    #[default]
+    XY,
+    /// Look at the XZ plane from the front.
+    XZ,
+    /// Look at the YZ plane from the side.
+    YZ,
+    /// Show all three axes at once in an isometric view.
+    Isometric,
+}
+
+impl SvgProjection {
+    pub fn project(self, p: Vec3) -> Vec2 {
+        match self {
+            SvgProjection::XY => Vec2::new(p.x, p.y),
+            SvgProjection::XZ => Vec2::new(p.x, p.z),
+            SvgProjection::YZ => Vec2::new(p.y, p.z),
+            SvgProjection::Isometric => {
+                let (sin, cos) = 30_f32.to_radians().sin_cos();
+                Vec2::new((p.x - p.y) * cos, p.z + (p.x + p.y) * sin)
+            }
+        }
+    }
+}
+
+impl Sketch {
+    pub fn export_svg(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.export_svg_with(path, SvgProjection::default())
+    }
+
+    pub fn export_svg_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        projection: SvgProjection,
+    ) -> std::io::Result<()> {
+        std::fs::write(path, self.evaluate().to_svg(projection))
+    }
+}
+
+impl Drawing {
+    pub fn to_svg(&self, projection: SvgProjection) -> String {
+        // SVG puts the y axis downwards while sketches put it upwards.
+        let project = |p: Vec3| {
+            let p = projection.project(p);
+            Vec2::new(p.x, -p.y)
+        };
+
+        let mut paths = String::new();
+        let mut min = Vec2::INFINITY;
+        let mut max = Vec2::NEG_INFINITY;
+        for stroke in self.pens.iter().flat_map(|p| &p.strokes) {
+            let (width, scaling) = match stroke.stroke {
+                Stroke::Volume(width) | Stroke::Ribbon(width) => (width, ""),
+                Stroke::Pixels(width) => {
+                    (width as f32, r#" vector-effect="non-scaling-stroke""#)
+                }
+            };
+
+            let mut d = String::new();
+            for (i, p) in stroke.points().map(project).enumerate() {
+                let command = if i == 0 { 'M' } else { 'L' };
+                let _ = write!(d, "{command}{} {} ", p.x, p.y);
+
+                let margin = match stroke.stroke {
+                    Stroke::Pixels(_) => 0.0,
+                    _ => width / 2.0,
+                };
+                min = min.min(p - margin);
+                max = max.max(p + margin);
+            }
+
+            let color = stroke.color.to_srgba();
+            let _ = writeln!(
+                paths,
+                r#"  <path d="{}" fill="none" stroke="{}" stroke-opacity="{}" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"{} />"#,
+                d.trim_end(),
+                color.with_alpha(1.0).to_hex(),
+                color.alpha(),
+                width,
+                scaling,
+            );
+        }
+
+        if min.x > max.x || min.y > max.y {
+            min = Vec2::ZERO;
+            max = Vec2::ONE;
+        }
+        let size = (max - min).max(Vec2::splat(f32::EPSILON));
+
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n{}</svg>\n",
+            min.x, min.y, size.x, size.y, paths,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Color;
+
+    use super::*;
+    use crate::Pen;
+
+    fn view_box(svg: &str) -> Vec<f32> {
+        let start = svg.find("viewBox=\"").unwrap() + "viewBox=\"".len();
+        let end = start + svg[start..].find('"').unwrap();
+        svg[start..end].split(' ').map(|v| v.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn every_stroke_becomes_one_path() {
+        let mut sketch = Sketch::headless();
+        let mut pen = sketch.spawn_pen(Color::WHITE);
+        pen.draw_forward(0.2);
+        pen.pen_up();
+        pen.move_to(Vec3::new(0.3, 0.0, 0.0));
+        pen.pen_down();
+        pen.draw_forward(0.2);
+        sketch
+            .spawn_pen(Pen { stroke: Stroke::Pixels(3), ..Color::BLACK.into() })
+            .draw_arc(0.5, 90.0);
+
+        let drawing = sketch.evaluate();
+        let strokes = drawing.pens.iter().map(|p| p.strokes.len()).sum::<usize>();
+        assert_eq!(strokes, 3);
+
+        let svg = drawing.to_svg(SvgProjection::XY);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<path ").count(), strokes);
+        assert_eq!(svg.matches(" />\n").count(), strokes);
+        assert_eq!(svg.matches("vector-effect=\"non-scaling-stroke\"").count(), 1);
+    }
+
+    #[test]
+    fn the_view_box_fits_the_strokes_with_y_pointing_up() {
+        let mut sketch = Sketch::headless();
+        let mut pen = sketch.spawn_pen(Pen { stroke: Stroke::Volume(0.1), ..Color::WHITE.into() });
+        pen.turn_left(90.0);
+        pen.draw_forward(1.0);
+
+        let svg = sketch.evaluate().to_svg(SvgProjection::XY);
+        let expected = [-0.05, -1.05, 0.1, 1.1];
+        for (value, expected) in view_box(&svg).into_iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-4, "{svg}");
+        }
+    }
+
+    #[test]
+    fn an_empty_drawing_is_still_a_valid_svg() {
+        let svg = Sketch::headless().evaluate().to_svg(SvgProjection::Isometric);
+        assert_eq!(view_box(&svg), [0.0, 0.0, 1.0, 1.0]);
+        assert!(!svg.contains("<path"));
+    }
+}
@@ -15,16 +15,43 @@
  *
 */
 
+mod camera;
+pub use camera::*;
+
+mod capture;
+pub use capture::*;
+
 mod crab;
 pub use crab::*;
 
+mod drawing;
+pub use drawing::*;
+
+mod export;
+pub use export::*;
+
+mod logo;
+pub use logo::*;
+
 mod pen;
 pub use pen::*;
 
+mod playback;
+pub(crate) use playback::*;
+
 mod schedule;
 pub(crate) use schedule::*;
 
 mod sketch;
 pub use sketch::*;
 
+mod source_overlay;
+pub(crate) use source_overlay::*;
+
+mod stroke;
+pub(crate) use stroke::*;
+
+mod transport;
+pub(crate) use transport::*;
+
 pub use bevy::math::{Vec2, Vec3};
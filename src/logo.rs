@@ -0,0 +1,595 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+mod parse;
+mod reload;
+use parse::{ColorArgument, Expression, Function, Operator, Program, Statement, StatementKind};
+
+use std::{borrow::Cow, collections::HashMap};
+
+use bevy::prelude::{Color, Vec3};
+
+use crate::{CallSite, PenCommands};
+
+/// How deeply procedures and the blocks inside them may nest while the script
+/// runs before it is assumed to be stuck in a loop. Recursive drawings like
+/// trees and snowflakes rarely go more than a few dozen levels deep.
+const MAX_DEPTH: usize = 1000;
+
+/// Stack for the thread that scripts run on. Each level of [`MAX_DEPTH`]
+/// takes a few kilobytes in debug builds, so this leaves room to spare no
+/// matter which thread the script was started from.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// How many pen actions a script may give before it is assumed to be stuck in
+/// a loop.
+const MAX_ACTIONS: usize = 100_000;
+
+/// How many statements and loop iterations a script may carry out before it is
+/// assumed to be stuck in a loop, even if it gives no pen actions.
+const MAX_STEPS: usize = 1_000_000;
+
+/// A script written in a small dialect of Logo that can be run on a pen.
+///
+/// ```text
+/// TO square :size
+///   REPEAT 4 [ FORWARD :size RIGHT 90 ]
+/// END
+///
+/// SETCOLOR "red
+/// square 50
+/// PENUP FORWARD 80 PENDOWN
+/// MAKE "size 20
+/// SETCOLOR [0 128 255]
+/// square :size * 2
+/// ```
+///
+/// The turtle starts off facing up along the Y axis, just like in Logo, and
+/// headings are measured clockwise from there.
+#[derive(Debug, Clone)]
+pub struct LogoScript {
+    name: String,
+    program: Program,
+    step_size: f32,
+}
+
+/// Something that is wrong with a Logo script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogoError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl LogoError {
+    fn new(position: Position, message: impl Into<String>) -> Self {
+        Self {
+            line: position.line,
+            column: position.column,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for LogoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for LogoError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl LogoScript {
+    pub fn parse(source: &str) -> Result<Self, LogoError> {
+        Ok(Self {
+            name: "logo".to_owned(),
+            program: parse::parse(source)?,
+            step_size: 0.002,
+        })
+    }
+
+    /// The name that the script goes by when playback shows which line each
+    /// pen is carrying out, usually the name of its file.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// How far one turtle step moves the pen. By default `FORWARD 100` moves
+    /// the pen 0.2 units.
+    pub fn with_step_size(mut self, step_size: f32) -> Self {
+        self.step_size = step_size;
+        self
+    }
+
+    /// Carry out the script with a pen. Nothing is given to the pen unless the
+    /// whole script runs without any errors.
+    pub fn run(&self, pen: &mut PenCommands) -> Result<(), LogoError> {
+        let actions = self.interpret()?;
+        self.replay(actions, pen);
+        Ok(())
+    }
+
+    fn interpret(&self) -> Result<Vec<(TurtleAction, Position)>, LogoError> {
+        let interpret = || {
+            let mut interpreter = Interpreter {
+                program: &self.program,
+                scopes: vec![HashMap::new()],
+                repeats: Vec::new(),
+                actions: Vec::new(),
+                steps: 0,
+            };
+            interpreter.block(&self.program.body, 0)?;
+            Ok(interpreter.actions)
+        };
+
+        // The interpreter recurses for every level of the script, so give it
+        // a stack that is big enough for MAX_DEPTH.
+        std::thread::scope(|scope| {
+            let thread = std::thread::Builder::new()
+                .name("logo".to_owned())
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, interpret)
+                .map_err(|error| {
+                    LogoError::new(
+                        Position { line: 1, column: 1 },
+                        format!("could not start running the script: {error}"),
+                    )
+                })?;
+            thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    fn replay(&self, actions: Vec<(TurtleAction, Position)>, pen: &mut PenCommands) {
+        let call_site = |position: Position, action: TurtleAction| CallSite {
+            file: Cow::Owned(self.name.clone()),
+            line: position.line as u32,
+            call: action.to_string(),
+        };
+
+        // Face up to begin with, without taking any time over it.
+        let start = Position { line: 1, column: 1 };
+        pen.source = Some(call_site(start, TurtleAction::SetHeading(0.0)));
+        pen.with_duration(0.0).set_heading(90.0);
+
+        let step = self.step_size;
+        for (action, position) in actions {
+            pen.source = Some(call_site(position, action));
+            match action {
+                TurtleAction::Forward(distance) => pen.draw_forward(step * distance),
+                TurtleAction::Back(distance) => pen.draw_backward(step * distance),
+                TurtleAction::Right(degrees) => pen.turn_right(degrees),
+                TurtleAction::Left(degrees) => pen.turn_left(degrees),
+                TurtleAction::PenUp => pen.pen_up(),
+                TurtleAction::PenDown => pen.pen_down(),
+                TurtleAction::SetColor(color) => pen.set_color(color),
+                TurtleAction::SetXY(x, y) => pen.draw_to(Vec3::new(step * x, step * y, 0.0)),
+                TurtleAction::SetHeading(heading) => pen.set_heading(90.0 - heading),
+            }
+        }
+        pen.source = None;
+    }
+}
+
+impl PenCommands<'_, '_> {
+    /// Parse a Logo script and carry it out with this pen. See [`LogoScript`]
+    /// for what the scripts look like.
+    pub fn run_logo(&mut self, source: &str) -> Result<(), LogoError> {
+        LogoScript::parse(source)?.run(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TurtleAction {
+    Forward(f32),
+    Back(f32),
+    Right(f32),
+    Left(f32),
+    PenUp,
+    PenDown,
+    SetColor(Color),
+    SetXY(f32, f32),
+    SetHeading(f32),
+}
+
+impl std::fmt::Display for TurtleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TurtleAction::Forward(distance) => write!(f, "FORWARD {distance}"),
+            TurtleAction::Back(distance) => write!(f, "BACK {distance}"),
+            TurtleAction::Right(degrees) => write!(f, "RIGHT {degrees}"),
+            TurtleAction::Left(degrees) => write!(f, "LEFT {degrees}"),
+            TurtleAction::PenUp => write!(f, "PENUP"),
+            TurtleAction::PenDown => write!(f, "PENDOWN"),
+            TurtleAction::SetColor(color) => write!(f, "SETCOLOR {}", color.to_srgba().to_hex()),
+            TurtleAction::SetXY(x, y) => write!(f, "SETXY {x} {y}"),
+            TurtleAction::SetHeading(heading) => write!(f, "SETHEADING {heading}"),
+        }
+    }
+}
+
+/// Whether a block ran to its end or hit a `STOP`.
+enum Flow {
+    Continue,
+    Stop,
+}
+
+struct Interpreter<'a> {
+    program: &'a Program,
+    /// Variables of each procedure that is running, with the global variables
+    /// at the bottom.
+    scopes: Vec<HashMap<String, f32>>,
+    /// The count of each `REPEAT` that is running, for `REPCOUNT`.
+    repeats: Vec<f32>,
+    actions: Vec<(TurtleAction, Position)>,
+    /// How many statements and loop iterations have been carried out.
+    steps: usize,
+}
+
+impl Interpreter<'_> {
+    fn block(&mut self, statements: &[Statement], depth: usize) -> Result<Flow, LogoError> {
+        for statement in statements {
+            if let Flow::Stop = self.statement(statement, depth)? {
+                return Ok(Flow::Stop);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn statement(&mut self, statement: &Statement, depth: usize) -> Result<Flow, LogoError> {
+        let position = statement.position;
+        self.step(position)?;
+        let action = match &statement.kind {
+            StatementKind::Forward(e) => TurtleAction::Forward(self.evaluate(e)?),
+            StatementKind::Back(e) => TurtleAction::Back(self.evaluate(e)?),
+            StatementKind::Right(e) => TurtleAction::Right(self.evaluate(e)?),
+            StatementKind::Left(e) => TurtleAction::Left(self.evaluate(e)?),
+            StatementKind::PenUp => TurtleAction::PenUp,
+            StatementKind::PenDown => TurtleAction::PenDown,
+            StatementKind::SetColor(color) => TurtleAction::SetColor(self.color(color, position)?),
+            StatementKind::SetXY(x, y) => TurtleAction::SetXY(self.evaluate(x)?, self.evaluate(y)?),
+            StatementKind::SetHeading(e) => TurtleAction::SetHeading(self.evaluate(e)?),
+            StatementKind::Home => {
+                self.push(TurtleAction::SetXY(0.0, 0.0), position)?;
+                TurtleAction::SetHeading(0.0)
+            }
+            StatementKind::Repeat(count, body) => {
+                let count = self.evaluate(count)?.round();
+                for i in 0..count.max(0.0) as usize {
+                    self.step(position)?;
+                    self.repeats.push(i as f32 + 1.0);
+                    let flow = self.block(body, depth + 1);
+                    self.repeats.pop();
+                    if let Flow::Stop = flow? {
+                        return Ok(Flow::Stop);
+                    }
+                }
+                return Ok(Flow::Continue);
+            }
+            StatementKind::If(condition, body) => {
+                if self.evaluate(condition)? != 0.0 {
+                    return self.block(body, depth + 1);
+                }
+                return Ok(Flow::Continue);
+            }
+            StatementKind::IfElse(condition, yes, no) => {
+                let body = if self.evaluate(condition)? != 0.0 { yes } else { no };
+                return self.block(body, depth + 1);
+            }
+            StatementKind::Make(name, value) => {
+                let value = self.evaluate(value)?;
+                let scope = self
+                    .scopes
+                    .iter_mut()
+                    .rev()
+                    .find(|scope| scope.contains_key(name));
+                match scope {
+                    Some(scope) => scope.insert(name.clone(), value),
+                    None => self.scopes[0].insert(name.clone(), value),
+                };
+                return Ok(Flow::Continue);
+            }
+            StatementKind::Stop => return Ok(Flow::Stop),
+            StatementKind::Call(name, arguments) => {
+                if depth >= MAX_DEPTH {
+                    return Err(LogoError::new(
+                        position,
+                        format!("{name} went more than {MAX_DEPTH} levels deep; is it missing a STOP?"),
+                    ));
+                }
+
+                let procedure = &self.program.procedures[name];
+                let mut scope = HashMap::new();
+                for (parameter, argument) in procedure.parameters.iter().zip(arguments) {
+                    scope.insert(parameter.clone(), self.evaluate(argument)?);
+                }
+
+                self.scopes.push(scope);
+                let flow = self.block(&procedure.body, depth + 1);
+                self.scopes.pop();
+                // STOP only leaves the procedure that it is in.
+                flow?;
+                return Ok(Flow::Continue);
+            }
+        };
+
+        self.push(action, position)?;
+        Ok(Flow::Continue)
+    }
+
+    fn step(&mut self, position: Position) -> Result<(), LogoError> {
+        if self.steps >= MAX_STEPS {
+            return Err(LogoError::new(
+                position,
+                format!("the script took more than {MAX_STEPS} steps; does it ever stop?"),
+            ));
+        }
+
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn push(&mut self, action: TurtleAction, position: Position) -> Result<(), LogoError> {
+        if self.actions.len() >= MAX_ACTIONS {
+            return Err(LogoError::new(
+                position,
+                format!("the script gave more than {MAX_ACTIONS} commands; does it ever stop?"),
+            ));
+        }
+
+        self.actions.push((action, position));
+        Ok(())
+    }
+
+    fn color(&mut self, color: &ColorArgument, position: Position) -> Result<Color, LogoError> {
+        match color {
+            ColorArgument::Name(name) => named_color(name).ok_or_else(|| {
+                LogoError::new(position, format!("I don't know the color \"{name}"))
+            }),
+            ColorArgument::Rgb(rgb) => {
+                let mut channels = [0.0; 3];
+                for (channel, e) in channels.iter_mut().zip(rgb.iter()) {
+                    *channel = self.evaluate(e)?.clamp(0.0, 255.0) / 255.0;
+                }
+                Ok(Color::srgb(channels[0], channels[1], channels[2]))
+            }
+            ColorArgument::Palette(index) => {
+                let index = self.evaluate(index)?.round();
+                PALETTE
+                    .get(index as usize)
+                    .filter(|_| index >= 0.0)
+                    .map(|[r, g, b]| Color::srgb_u8(*r, *g, *b))
+                    .ok_or_else(|| {
+                        LogoError::new(position, format!("color numbers go from 0 to 15, not {index}"))
+                    })
+            }
+        }
+    }
+
+    fn evaluate(&self, expression: &Expression) -> Result<f32, LogoError> {
+        let value = match expression {
+            Expression::Number(value) => *value,
+            Expression::Variable(name, position) => *self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name))
+                .ok_or_else(|| LogoError::new(*position, format!(":{name} has no value")))?,
+            Expression::RepCount(position) => *self.repeats.last().ok_or_else(|| {
+                LogoError::new(*position, "REPCOUNT can only be used inside a REPEAT")
+            })?,
+            Expression::Negate(inner) => -self.evaluate(inner)?,
+            Expression::Binary(op, lhs, rhs, position) => {
+                let (lhs, rhs) = (self.evaluate(lhs)?, self.evaluate(rhs)?);
+                let truth = |b: bool| if b { 1.0 } else { 0.0 };
+                match op {
+                    Operator::Add => lhs + rhs,
+                    Operator::Subtract => lhs - rhs,
+                    Operator::Multiply => lhs * rhs,
+                    Operator::Divide => {
+                        if rhs == 0.0 {
+                            return Err(LogoError::new(*position, "cannot divide by zero"));
+                        }
+                        lhs / rhs
+                    }
+                    Operator::Less => truth(lhs < rhs),
+                    Operator::Greater => truth(lhs > rhs),
+                    Operator::Equal => truth(lhs == rhs),
+                }
+            }
+            Expression::Function(function, input) => {
+                let input = self.evaluate(input)?;
+                match function {
+                    Function::Sqrt => input.sqrt(),
+                    Function::Sin => input.to_radians().sin(),
+                    Function::Cos => input.to_radians().cos(),
+                    Function::Abs => input.abs(),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+/// The standard sixteen Logo pen colors, picked by number.
+const PALETTE: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [0, 0, 255],
+    [0, 255, 0],
+    [0, 255, 255],
+    [255, 0, 0],
+    [255, 0, 255],
+    [255, 255, 0],
+    [255, 255, 255],
+    [155, 96, 59],
+    [197, 136, 18],
+    [100, 162, 64],
+    [120, 187, 187],
+    [255, 149, 119],
+    [144, 113, 208],
+    [255, 163, 0],
+    [183, 183, 183],
+];
+
+fn named_color(name: &str) -> Option<Color> {
+    let [r, g, b] = match name {
+        "black" => PALETTE[0],
+        "blue" => PALETTE[1],
+        "green" => PALETTE[2],
+        "cyan" => PALETTE[3],
+        "red" => PALETTE[4],
+        "magenta" => PALETTE[5],
+        "yellow" => PALETTE[6],
+        "white" => PALETTE[7],
+        "brown" => PALETTE[8],
+        "purple" => PALETTE[13],
+        "orange" => PALETTE[14],
+        "gray" | "grey" => PALETTE[15],
+        "pink" => [255, 192, 203],
+        _ => return None,
+    };
+    Some(Color::srgb_u8(r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Vec<String> {
+        let script = LogoScript::parse(source).unwrap();
+        script.interpret().unwrap().iter().map(|(action, _)| action.to_string()).collect()
+    }
+
+    fn error(source: &str) -> String {
+        LogoScript::parse(source).unwrap().interpret().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn repeat_counts_each_time_around() {
+        assert_eq!(run("REPEAT 3 [ FD REPCOUNT * 10 ]"), ["FORWARD 10", "FORWARD 20", "FORWARD 30"]);
+        assert_eq!(
+            run("REPEAT 2 [ REPEAT 2 [ RT REPCOUNT ] LT REPCOUNT ]"),
+            ["RIGHT 1", "RIGHT 2", "LEFT 1", "RIGHT 1", "RIGHT 2", "LEFT 2"],
+        );
+        assert!(run("REPEAT -2 [ FD 1 ] REPEAT 0 [ FD 1 ]").is_empty());
+        assert_eq!(error("FD 1\nFD REPCOUNT"), "2:4: REPCOUNT can only be used inside a REPEAT");
+    }
+
+    #[test]
+    fn if_picks_a_block() {
+        let source = "MAKE \"a 3
+            IF :a > 2 [ FD 1 ]
+            IF :a < 2 [ FD 2 ]
+            IFELSE :a = 3 [ BK 1 ] [ BK 2 ]
+            IFELSE :a = 4 [ BK 3 ] [ BK 4 ]";
+        assert_eq!(run(source), ["FORWARD 1", "BACK 1", "BACK 4"]);
+    }
+
+    #[test]
+    fn stop_leaves_only_the_procedure_it_is_in() {
+        let source = "TO spiral :n
+              IF :n > 30 [ STOP ]
+              FD :n RT 90
+              spiral :n + 10
+            END
+            TO once
+              REPEAT 5 [ FD REPCOUNT IF REPCOUNT = 2 [ STOP ] ]
+              FD 100
+            END
+            spiral 10 once PU";
+        assert_eq!(
+            run(source),
+            [
+                "FORWARD 10", "RIGHT 90", "FORWARD 20", "RIGHT 90", "FORWARD 30", "RIGHT 90",
+                "FORWARD 1", "FORWARD 2", "PENUP",
+            ],
+        );
+
+        // STOP outside of any procedure ends the script.
+        assert_eq!(run("FD 1 STOP FD 2"), ["FORWARD 1"]);
+    }
+
+    #[test]
+    fn procedures_see_their_own_inputs_first() {
+        let source = "MAKE \"x 1
+            TO shadow :x
+              FD :x MAKE \"x 5 FD :x
+              peek
+            END
+            TO peek FD :x END
+            TO bump MAKE \"x :x + 1 END
+            shadow 10
+            FD :x
+            bump bump
+            FD :x";
+        assert_eq!(
+            run(source),
+            ["FORWARD 10", "FORWARD 5", "FORWARD 5", "FORWARD 1", "FORWARD 3"],
+        );
+
+        // Inputs are gone once the procedure that has them is finished.
+        assert_eq!(
+            error("TO square :size FD :size END\nsquare 10\nFD :size"),
+            "3:4: :SIZE has no value",
+        );
+    }
+
+    #[test]
+    fn runaway_scripts_are_stopped() {
+        assert!(error("TO forever\n  FD 1 forever\nEND\nforever")
+            .starts_with(&format!("2:8: FOREVER went more than {MAX_DEPTH} levels deep")));
+        assert!(error("FD 1\nREPEAT 1e6 [ FD 1 ]")
+            .starts_with(&format!("2:14: the script gave more than {MAX_ACTIONS} commands")));
+
+        // Loops that never give the pen anything still have to end.
+        let steps = format!("the script took more than {MAX_STEPS} steps");
+        assert!(error("FD 1\nREPEAT 1e12 [ ]").starts_with(&format!("2:1: {steps}")));
+        assert!(error("REPEAT 1e30 [ MAKE \"x 1 ]").contains(&steps));
+        assert!(error("TO spin :n IF :n > 0 [ spin :n - 1 ] END\nREPEAT 1e9 [ spin 10 ]")
+            .contains(&steps));
+    }
+
+    #[test]
+    fn the_deepest_scripts_run_from_any_thread() {
+        // Every level of the recursion evaluates an input that is nested as
+        // deeply as the parser allows.
+        let depth = parse::MAX_NESTING - 2;
+        let source = format!(
+            "TO down :n\n  FD {}:n{}\n  IF 1 [ REPEAT 1 [ down :n + 1 ] ]\nEND\ndown 0",
+            "(".repeat(depth),
+            ")".repeat(depth),
+        );
+        let script = LogoScript::parse(&source).unwrap();
+
+        // Scripts get a stack of their own, so even a thread with a small
+        // stack can run them.
+        let error = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || script.interpret().unwrap_err())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(error.message.contains(&format!("went more than {MAX_DEPTH} levels deep")));
+    }
+}
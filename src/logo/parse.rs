@@ -0,0 +1,771 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::collections::HashMap;
+
+use super::{LogoError, Position};
+
+/// How deeply brackets, parentheses and negations may nest. The parser and
+/// the interpreter recurse into each one, so this keeps them on the stack.
+pub(crate) const MAX_NESTING: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f32),
+    /// `:name`
+    Variable(String),
+    /// `"name`
+    Quoted(String),
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+    Operator(Operator),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Number(value) => write!(f, "{value}"),
+            Token::Variable(name) => write!(f, ":{name}"),
+            Token::Quoted(word) => write!(f, "\"{word}"),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::Operator(op) => write!(f, "{}", op.symbol()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Less,
+    Greater,
+    Equal,
+}
+
+impl Operator {
+    fn symbol(self) -> char {
+        match self {
+            Operator::Add => '+',
+            Operator::Subtract => '-',
+            Operator::Multiply => '*',
+            Operator::Divide => '/',
+            Operator::Less => '<',
+            Operator::Greater => '>',
+            Operator::Equal => '=',
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    position: Position,
+    /// A `-` with a space before it but not after it, like the one in
+    /// `SETXY 10 -20`, negates what follows instead of subtracting.
+    negates: bool,
+}
+
+struct Cursor<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    position: Position,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek().filter(|c| is_word_char(*c)) {
+            word.push(c);
+            self.bump();
+        }
+        word
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '?'
+}
+
+fn tokenize(source: &str) -> Result<Vec<Spanned>, LogoError> {
+    let mut tokens = Vec::new();
+    let mut cursor = Cursor {
+        chars: source.chars().peekable(),
+        position: Position { line: 1, column: 1 },
+    };
+    let mut after_space = true;
+
+    while let Some(c) = cursor.peek() {
+        let start = cursor.position;
+        if c.is_whitespace() {
+            cursor.bump();
+            after_space = true;
+            continue;
+        }
+
+        if c == ';' {
+            // Comments run to the end of the line.
+            while cursor.peek().is_some_and(|c| c != '\n') {
+                cursor.bump();
+            }
+            continue;
+        }
+
+        let mut negates = false;
+        let token = match c {
+            '[' | ']' | '(' | ')' => {
+                cursor.bump();
+                match c {
+                    '[' => Token::OpenBracket,
+                    ']' => Token::CloseBracket,
+                    '(' => Token::OpenParen,
+                    _ => Token::CloseParen,
+                }
+            }
+            '+' | '-' | '*' | '/' | '<' | '>' | '=' => {
+                cursor.bump();
+                let op = match c {
+                    '+' => Operator::Add,
+                    '-' => Operator::Subtract,
+                    '*' => Operator::Multiply,
+                    '/' => Operator::Divide,
+                    '<' => Operator::Less,
+                    '>' => Operator::Greater,
+                    _ => Operator::Equal,
+                };
+                negates = op == Operator::Subtract
+                    && after_space
+                    && cursor.peek().is_some_and(|c| !c.is_whitespace());
+                Token::Operator(op)
+            }
+            ':' | '"' => {
+                cursor.bump();
+                let name = cursor.word();
+                if name.is_empty() {
+                    return Err(LogoError::new(start, format!("expected a name after {c}")));
+                }
+                if c == ':' {
+                    Token::Variable(name.to_uppercase())
+                } else {
+                    Token::Quoted(name)
+                }
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let text = cursor.word();
+                match text.parse() {
+                    Ok(value) => Token::Number(value),
+                    Err(_) => {
+                        return Err(LogoError::new(start, format!("{text} is not a number")));
+                    }
+                }
+            }
+            c if is_word_char(c) => Token::Word(cursor.word().to_uppercase()),
+            c => {
+                return Err(LogoError::new(start, format!("unexpected character {c}")));
+            }
+        };
+
+        tokens.push(Spanned { token, position: start, negates });
+        after_space = false;
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Expression {
+    Number(f32),
+    Variable(String, Position),
+    RepCount(Position),
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>, Position),
+    Function(Function, Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Function {
+    Sqrt,
+    Sin,
+    Cos,
+    Abs,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ColorArgument {
+    Name(String),
+    Rgb(Box<[Expression; 3]>),
+    Palette(Expression),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Statement {
+    pub(crate) kind: StatementKind,
+    pub(crate) position: Position,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum StatementKind {
+    Forward(Expression),
+    Back(Expression),
+    Right(Expression),
+    Left(Expression),
+    PenUp,
+    PenDown,
+    SetColor(ColorArgument),
+    SetXY(Expression, Expression),
+    SetHeading(Expression),
+    Home,
+    Repeat(Expression, Vec<Statement>),
+    If(Expression, Vec<Statement>),
+    IfElse(Expression, Vec<Statement>, Vec<Statement>),
+    Make(String, Expression),
+    Stop,
+    Call(String, Vec<Expression>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Procedure {
+    pub(crate) parameters: Vec<String>,
+    pub(crate) body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Program {
+    pub(crate) procedures: HashMap<String, Procedure>,
+    pub(crate) body: Vec<Statement>,
+}
+
+const BUILT_IN: &[&str] = &[
+    "FORWARD", "FD", "BACK", "BK", "RIGHT", "RT", "LEFT", "LT", "PENUP", "PU", "PENDOWN", "PD",
+    "SETCOLOR", "SETPC", "SETPENCOLOR", "SETXY", "SETHEADING", "SETH", "HOME", "REPEAT", "IF",
+    "IFELSE", "MAKE", "STOP", "TO", "END", "REPCOUNT", "SQRT", "SIN", "COS", "ABS",
+];
+
+pub(crate) fn parse(source: &str) -> Result<Program, LogoError> {
+    let tokens = tokenize(source)?;
+    let end = tokens.last().map_or(Position { line: 1, column: 1 }, |t| t.position);
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        end,
+        arity: HashMap::new(),
+        nesting: 0,
+    };
+    parser.declare_procedures()?;
+
+    let mut program = Program::default();
+    while let Some(spanned) = parser.peek().cloned() {
+        if spanned.token == Token::Word("TO".to_owned()) {
+            let (name, procedure) = parser.procedure()?;
+            program.procedures.insert(name, procedure);
+        } else {
+            program.body.push(parser.statement()?);
+        }
+    }
+
+    Ok(program)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    next: usize,
+    /// Where the script ends, for complaining about things that are missing.
+    end: Position,
+    /// How many inputs each procedure takes, so calls can be parsed without
+    /// needing parentheses.
+    arity: HashMap<String, usize>,
+    /// How many blocks and expressions are being parsed inside each other.
+    nesting: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Spanned> {
+        let spanned = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        spanned
+    }
+
+    fn expect(&mut self, what: &str) -> Result<Spanned, LogoError> {
+        self.advance().ok_or_else(|| {
+            LogoError::new(self.end, format!("expected {what} but the script ended"))
+        })
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, LogoError>,
+    ) -> Result<T, LogoError> {
+        if self.nesting >= MAX_NESTING {
+            let position = self.peek().map_or(self.end, |s| s.position);
+            return Err(LogoError::new(
+                position,
+                format!("this is nested more than {MAX_NESTING} levels deep"),
+            ));
+        }
+
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    /// Find every `TO` up front so that procedures can be used before they
+    /// are defined.
+    fn declare_procedures(&mut self) -> Result<(), LogoError> {
+        for (i, spanned) in self.tokens.iter().enumerate() {
+            if spanned.token != Token::Word("TO".to_owned()) {
+                continue;
+            }
+
+            let Some(Spanned { token: Token::Word(name), position, .. }) = self.tokens.get(i + 1)
+            else {
+                return Err(LogoError::new(spanned.position, "TO needs a name for the procedure"));
+            };
+
+            if BUILT_IN.contains(&name.as_str()) {
+                return Err(LogoError::new(*position, format!("{name} is already a command")));
+            }
+
+            if self.arity.contains_key(name) {
+                return Err(LogoError::new(*position, format!("{name} is already defined")));
+            }
+
+            let inputs = self.tokens[i + 2..]
+                .iter()
+                .take_while(|t| matches!(t.token, Token::Variable(_)))
+                .count();
+            self.arity.insert(name.clone(), inputs);
+        }
+
+        Ok(())
+    }
+
+    fn procedure(&mut self) -> Result<(String, Procedure), LogoError> {
+        let to = self.expect("TO")?;
+        let Some(Spanned { token: Token::Word(name), .. }) = self.advance() else {
+            return Err(LogoError::new(to.position, "TO needs a name for the procedure"));
+        };
+
+        let mut parameters = Vec::new();
+        while let Some(Spanned { token: Token::Variable(parameter), .. }) = self.peek() {
+            parameters.push(parameter.clone());
+            self.next += 1;
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let Some(spanned) = self.peek() else {
+                return Err(LogoError::new(to.position, format!("{name} is missing its END")));
+            };
+
+            match &spanned.token {
+                Token::Word(word) if word == "END" => {
+                    self.next += 1;
+                    break;
+                }
+                Token::Word(word) if word == "TO" => {
+                    return Err(LogoError::new(
+                        spanned.position,
+                        format!("{name} is missing its END before the next TO"),
+                    ));
+                }
+                _ => body.push(self.statement()?),
+            }
+        }
+
+        Ok((name, Procedure { parameters, body }))
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, LogoError> {
+        self.nested(Self::bracketed)
+    }
+
+    fn bracketed(&mut self) -> Result<Vec<Statement>, LogoError> {
+        let open = self.expect("[")?;
+        if open.token != Token::OpenBracket {
+            return Err(LogoError::new(
+                open.position,
+                format!("expected [ but found {}", open.token),
+            ));
+        }
+
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(LogoError::new(open.position, "this [ is never closed")),
+                Some(Spanned { token: Token::CloseBracket, .. }) => {
+                    self.next += 1;
+                    return Ok(statements);
+                }
+                Some(_) => statements.push(self.statement()?),
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, LogoError> {
+        let spanned = self.expect("a command")?;
+        let position = spanned.position;
+        let Token::Word(word) = spanned.token else {
+            return Err(LogoError::new(
+                position,
+                format!("expected a command but found {}", spanned.token),
+            ));
+        };
+
+        let kind = match word.as_str() {
+            "FORWARD" | "FD" => StatementKind::Forward(self.expression()?),
+            "BACK" | "BK" => StatementKind::Back(self.expression()?),
+            "RIGHT" | "RT" => StatementKind::Right(self.expression()?),
+            "LEFT" | "LT" => StatementKind::Left(self.expression()?),
+            "PENUP" | "PU" => StatementKind::PenUp,
+            "PENDOWN" | "PD" => StatementKind::PenDown,
+            "SETCOLOR" | "SETPC" | "SETPENCOLOR" => StatementKind::SetColor(self.color()?),
+            "SETXY" => StatementKind::SetXY(self.expression()?, self.expression()?),
+            "SETHEADING" | "SETH" => StatementKind::SetHeading(self.expression()?),
+            "HOME" => StatementKind::Home,
+            "REPEAT" => StatementKind::Repeat(self.expression()?, self.block()?),
+            "IF" => StatementKind::If(self.expression()?, self.block()?),
+            "IFELSE" => StatementKind::IfElse(self.expression()?, self.block()?, self.block()?),
+            "MAKE" => {
+                let name = self.expect("a quoted name")?;
+                let Token::Quoted(name) = name.token else {
+                    return Err(LogoError::new(
+                        name.position,
+                        format!("MAKE needs a quoted name like \"size but found {}", name.token),
+                    ));
+                };
+                StatementKind::Make(name.to_uppercase(), self.expression()?)
+            }
+            "STOP" => StatementKind::Stop,
+            "TO" => {
+                return Err(LogoError::new(position, "procedures can only be defined at the top level"));
+            }
+            "END" => return Err(LogoError::new(position, "END without a TO")),
+            "REPCOUNT" | "SQRT" | "SIN" | "COS" | "ABS" => {
+                return Err(LogoError::new(
+                    position,
+                    format!("{word} gives a value, so it needs a command to use it"),
+                ));
+            }
+            _ => {
+                let Some(&inputs) = self.arity.get(&word) else {
+                    return Err(LogoError::new(position, format!("I don't know how to {word}")));
+                };
+                let arguments = (0..inputs)
+                    .map(|_| self.expression())
+                    .collect::<Result<_, _>>()?;
+                StatementKind::Call(word, arguments)
+            }
+        };
+
+        Ok(Statement { kind, position })
+    }
+
+    fn color(&mut self) -> Result<ColorArgument, LogoError> {
+        match self.peek().map(|s| &s.token) {
+            Some(Token::Quoted(name)) => {
+                let name = name.to_lowercase();
+                self.next += 1;
+                Ok(ColorArgument::Name(name))
+            }
+            Some(Token::OpenBracket) => {
+                let open = self.expect("[")?;
+                let rgb = [self.expression()?, self.expression()?, self.expression()?];
+                match self.advance() {
+                    Some(Spanned { token: Token::CloseBracket, .. }) => {
+                        Ok(ColorArgument::Rgb(Box::new(rgb)))
+                    }
+                    _ => Err(LogoError::new(
+                        open.position,
+                        "a color list needs exactly three numbers, like [255 128 0]",
+                    )),
+                }
+            }
+            _ => Ok(ColorArgument::Palette(self.expression()?)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, LogoError> {
+        self.nested(Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, LogoError> {
+        let mut lhs = self.sum()?;
+        while let Some(op @ (Operator::Less | Operator::Greater | Operator::Equal)) = self.operator() {
+            let position = self.advance().map(|s| s.position).unwrap_or(self.end);
+            let rhs = self.sum()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs), position);
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<Expression, LogoError> {
+        let mut lhs = self.product()?;
+        while let Some(op @ (Operator::Add | Operator::Subtract)) = self.operator() {
+            if self.peek().is_some_and(|s| s.negates) {
+                // This starts the next input rather than continuing this one.
+                break;
+            }
+            let position = self.advance().map(|s| s.position).unwrap_or(self.end);
+            let rhs = self.product()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs), position);
+        }
+        Ok(lhs)
+    }
+
+    fn product(&mut self) -> Result<Expression, LogoError> {
+        let mut lhs = self.unary()?;
+        while let Some(op @ (Operator::Multiply | Operator::Divide)) = self.operator() {
+            let position = self.advance().map(|s| s.position).unwrap_or(self.end);
+            let rhs = self.unary()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs), position);
+        }
+        Ok(lhs)
+    }
+
+    fn operator(&self) -> Option<Operator> {
+        match self.peek()?.token {
+            Token::Operator(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, LogoError> {
+        if self.operator() == Some(Operator::Subtract) {
+            self.next += 1;
+            let inner = self.nested(Self::unary)?;
+            return Ok(Expression::Negate(Box::new(inner)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, LogoError> {
+        let spanned = self.expect("a number")?;
+        let position = spanned.position;
+        match spanned.token {
+            Token::Number(value) => Ok(Expression::Number(value)),
+            Token::Variable(name) => Ok(Expression::Variable(name, position)),
+            Token::OpenParen => {
+                let inner = self.expression()?;
+                match self.advance() {
+                    Some(Spanned { token: Token::CloseParen, .. }) => Ok(inner),
+                    _ => Err(LogoError::new(position, "this ( is never closed")),
+                }
+            }
+            Token::Word(word) => {
+                let function = match word.as_str() {
+                    "REPCOUNT" => return Ok(Expression::RepCount(position)),
+                    "SQRT" => Function::Sqrt,
+                    "SIN" => Function::Sin,
+                    "COS" => Function::Cos,
+                    "ABS" => Function::Abs,
+                    _ if self.arity.contains_key(&word) => {
+                        return Err(LogoError::new(
+                            position,
+                            format!("{word} does not give back a value"),
+                        ));
+                    }
+                    _ => {
+                        return Err(LogoError::new(
+                            position,
+                            format!("expected a number but found {word}"),
+                        ));
+                    }
+                };
+                Ok(Expression::Function(function, Box::new(self.expression()?)))
+            }
+            token => Err(LogoError::new(
+                position,
+                format!("expected a number but found {token}"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(expression: &Expression) -> String {
+        match expression {
+            Expression::Number(value) => value.to_string(),
+            Expression::Variable(name, _) => format!(":{name}"),
+            Expression::RepCount(_) => "REPCOUNT".to_owned(),
+            Expression::Negate(inner) => format!("(- {})", show(inner)),
+            Expression::Binary(op, lhs, rhs, _) => {
+                format!("({} {} {})", op.symbol(), show(lhs), show(rhs))
+            }
+            Expression::Function(function, input) => format!("({function:?} {})", show(input)),
+        }
+    }
+
+    fn statements(source: &str) -> Vec<StatementKind> {
+        parse(source).unwrap().body.into_iter().map(|s| s.kind).collect()
+    }
+
+    /// Show the inputs of the one statement in the source.
+    fn inputs(source: &str) -> Vec<String> {
+        let [kind] = &statements(source)[..] else {
+            panic!("expected one statement in {source}");
+        };
+        match kind {
+            StatementKind::Forward(e) | StatementKind::If(e, _) => vec![show(e)],
+            StatementKind::SetXY(x, y) => vec![show(x), show(y)],
+            StatementKind::Call(_, arguments) => arguments.iter().map(show).collect(),
+            kind => panic!("unexpected {kind:?}"),
+        }
+    }
+
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn every_statement_parses() {
+        let kinds = statements(
+            "FORWARD 1 FD 1 BACK 1 BK 1 RIGHT 1 RT 1 LEFT 1 LT 1 PENUP PU PENDOWN PD \
+             SETCOLOR \"Red SETPC [1 2 3] SETPENCOLOR 4 SETXY 1 2 SETHEADING 1 SETH 1 HOME \
+             REPEAT 2 [ FD 1 ] IF 1 [ ] IFELSE 1 [ ] [ FD 1 ] MAKE \"size 1 STOP",
+        );
+        let expected: &[fn(&StatementKind) -> bool] = &[
+            |k| matches!(k, StatementKind::Forward(_)),
+            |k| matches!(k, StatementKind::Forward(_)),
+            |k| matches!(k, StatementKind::Back(_)),
+            |k| matches!(k, StatementKind::Back(_)),
+            |k| matches!(k, StatementKind::Right(_)),
+            |k| matches!(k, StatementKind::Right(_)),
+            |k| matches!(k, StatementKind::Left(_)),
+            |k| matches!(k, StatementKind::Left(_)),
+            |k| matches!(k, StatementKind::PenUp),
+            |k| matches!(k, StatementKind::PenUp),
+            |k| matches!(k, StatementKind::PenDown),
+            |k| matches!(k, StatementKind::PenDown),
+            |k| matches!(k, StatementKind::SetColor(ColorArgument::Name(name)) if name == "red"),
+            |k| matches!(k, StatementKind::SetColor(ColorArgument::Rgb(_))),
+            |k| matches!(k, StatementKind::SetColor(ColorArgument::Palette(_))),
+            |k| matches!(k, StatementKind::SetXY(_, _)),
+            |k| matches!(k, StatementKind::SetHeading(_)),
+            |k| matches!(k, StatementKind::SetHeading(_)),
+            |k| matches!(k, StatementKind::Home),
+            |k| matches!(k, StatementKind::Repeat(_, body) if body.len() == 1),
+            |k| matches!(k, StatementKind::If(_, body) if body.is_empty()),
+            |k| matches!(k, StatementKind::IfElse(_, yes, no) if yes.is_empty() && no.len() == 1),
+            |k| matches!(k, StatementKind::Make(name, _) if name == "SIZE"),
+            |k| matches!(k, StatementKind::Stop),
+        ];
+        assert_eq!(kinds.len(), expected.len());
+        for (kind, expected) in kinds.iter().zip(expected) {
+            assert!(expected(kind), "unexpected {kind:?}");
+        }
+    }
+
+    #[test]
+    fn procedures_can_be_called_before_they_are_defined() {
+        let program = parse("square 10\nTO square :size\n  FD :size\nEND").unwrap();
+        let procedure = &program.procedures["SQUARE"];
+        assert_eq!(procedure.parameters, ["SIZE"]);
+        assert_eq!(procedure.body.len(), 1);
+        assert!(matches!(
+            &program.body[..],
+            [Statement { kind: StatementKind::Call(name, arguments), .. }]
+                if name == "SQUARE" && arguments.len() == 1
+        ));
+        assert_eq!(program.body[0].position, Position { line: 1, column: 1 });
+    }
+
+    #[test]
+    fn operators_have_precedence() {
+        assert_eq!(inputs("FD 1 + 2 * 3 - 4 / 2"), ["(- (+ 1 (* 2 3)) (/ 4 2))"]);
+        assert_eq!(inputs("FD (1 + 2) * 3"), ["(* (+ 1 2) 3)"]);
+        assert_eq!(inputs("IF 1 + 2 > 2 * 1 [ ]"), ["(> (+ 1 2) (* 2 1))"]);
+        assert_eq!(inputs("FD SQRT 4 + 5"), ["(Sqrt (+ 4 5))"]);
+        assert_eq!(inputs("FD -:a * 2"), ["(* (- :A) 2)"]);
+    }
+
+    #[test]
+    fn minus_negates_only_when_it_starts_an_input() {
+        assert_eq!(inputs("FORWARD -50"), ["(- 50)"]);
+        assert_eq!(inputs("FORWARD :a - 50"), ["(- :A 50)"]);
+        assert_eq!(inputs("FORWARD :a-50"), ["(- :A 50)"]);
+        assert_eq!(inputs("SETXY :a -50"), [":A", "(- 50)"]);
+        assert_eq!(inputs("SETXY :a - 50 0"), ["(- :A 50)", "0"]);
+        assert_eq!(inputs("TO two :x :y END two 1 -2"), ["1", "(- 2)"]);
+    }
+
+    #[test]
+    fn errors_point_at_where_they_are() {
+        assert_eq!(error("FD 10\nRT @"), "2:4: unexpected character @");
+        assert_eq!(error("FD 10\n  square 5"), "2:3: I don't know how to SQUARE");
+        assert_eq!(error("REPEAT 4 [ FD 10"), "1:10: this [ is never closed");
+        assert_eq!(error("FD (1 + 2"), "1:4: this ( is never closed");
+        assert_eq!(error("FD 10\nRT"), "2:1: expected a number but the script ended");
+        assert_eq!(error("FD 1.2.3"), "1:4: 1.2.3 is not a number");
+        assert_eq!(
+            error("MAKE size 1"),
+            "1:6: MAKE needs a quoted name like \"size but found SIZE",
+        );
+        assert_eq!(
+            error("TO square\nFD 1\nTO circle END"),
+            "3:1: SQUARE is missing its END before the next TO",
+        );
+        assert_eq!(error("TO forward END"), "1:4: FORWARD is already a command");
+        assert_eq!(error("\n\nEND"), "3:1: END without a TO");
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_instead_of_a_crash() {
+        let fits = MAX_NESTING - 1;
+        let parens = format!("FD {}1{}", "(".repeat(fits), ")".repeat(fits));
+        assert_eq!(inputs(&parens), ["1"]);
+
+        for source in [
+            format!("FD {}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+            format!("FD {}1", "-".repeat(100_000)),
+            format!("{}{}", "REPEAT 1 [ ".repeat(100_000), "]".repeat(100_000)),
+            format!("FD {}1", "SQRT ".repeat(100_000)),
+        ] {
+            assert!(error(&source).contains("nested more than"));
+        }
+    }
+}
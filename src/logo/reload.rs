@@ -0,0 +1,132 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::prelude::{
+    error, info, Color, IntoSystemConfigs, Res, ResMut, Resource, Time, Update, World,
+};
+
+use super::LogoScript;
+use crate::{play_schedule, ClearSketch, PenHandle, Sketch};
+
+/// How many seconds to wait between checking whether the script was saved.
+const CHECK_INTERVAL: f32 = 0.25;
+
+#[derive(Resource, Debug)]
+pub(crate) struct LogoFile {
+    path: PathBuf,
+    pen: PenHandle,
+    modified: Option<SystemTime>,
+    since_check: f32,
+    /// Set when the file has been saved since it was last run.
+    stale: bool,
+}
+
+impl Sketch {
+    /// Run a Logo script file with a new pen. Whenever the file is saved, the
+    /// sketch gets cleared and the script is run again from the start, so the
+    /// script can be worked on while the sketch stays open.
+    ///
+    /// Mistakes in the script get logged instead of stopping the sketch, so
+    /// that they can be fixed and saved. Only one file can be watched at a
+    /// time.
+    pub fn watch_logo(&mut self, path: impl Into<PathBuf>) -> std::io::Result<PenHandle> {
+        let path = path.into();
+        let source = fs::read_to_string(&path)?;
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let pen = self.spawn_pen(Color::WHITE).handle();
+
+        let world = self.app.world_mut();
+        run_script(world, &path, pen, &source);
+        if !world.contains_resource::<LogoFile>() {
+            self.app.add_systems(
+                Update,
+                (check_logo_file, reload_logo_file).chain().before(play_schedule),
+            );
+        }
+        self.app.insert_resource(LogoFile {
+            path,
+            pen,
+            modified,
+            since_check: 0.0,
+            stale: false,
+        });
+
+        Ok(pen)
+    }
+}
+
+fn check_logo_file(time: Res<Time>, mut file: ResMut<LogoFile>) {
+    file.since_check += time.delta_secs();
+    if file.since_check < CHECK_INTERVAL {
+        return;
+    }
+    file.since_check = 0.0;
+
+    // Editors sometimes replace the file instead of writing into it, so it
+    // may briefly be missing while it is being saved.
+    let Ok(modified) = fs::metadata(&file.path).and_then(|m| m.modified()) else {
+        return;
+    };
+
+    if file.modified != Some(modified) {
+        file.modified = Some(modified);
+        file.stale = true;
+    }
+}
+
+fn reload_logo_file(world: &mut World) {
+    let Some(mut file) = world.get_resource_mut::<LogoFile>() else {
+        return;
+    };
+
+    if !file.stale {
+        return;
+    }
+    file.stale = false;
+
+    let (path, pen) = (file.path.clone(), file.pen);
+    match fs::read_to_string(&path) {
+        Ok(source) => run_script(world, &path, pen, &source),
+        Err(err) => error!("Unable to read {}: {err}", path.display()),
+    }
+}
+
+/// Clear the sketch and run the script with the pen, unless the script has a
+/// mistake in it, in which case the sketch is left as it was.
+fn run_script(world: &mut World, path: &Path, pen: PenHandle, source: &str) {
+    let name = path.display().to_string();
+    let result = LogoScript::parse(source).and_then(|script| {
+        let script = script.with_name(name.clone());
+        let actions = script.interpret()?;
+        let mut commands = world.commands();
+        commands.queue(ClearSketch);
+        script.replay(actions, &mut pen.command(commands));
+        Ok(())
+    });
+    world.flush();
+
+    match result {
+        Ok(()) => info!("Running {name}"),
+        Err(err) => error!("{name}:{err}"),
+    }
+}
@@ -0,0 +1,47 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use std::process::ExitCode;
+
+use crab_edu::Sketch;
+
+const USAGE: &str = "usage: crab-edu run <script.logo>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [command, path] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    if command != "run" {
+        eprintln!("unknown command {command}\n{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut sketch = Sketch::new();
+    if let Err(err) = sketch.watch_logo(path) {
+        eprintln!("unable to open {path}: {err}");
+        return ExitCode::FAILURE;
+    }
+
+    if sketch.run().is_success() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
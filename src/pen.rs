@@ -15,29 +15,32 @@
  *
 */
 
+use std::{borrow::Cow, panic::Location};
+
 use bevy::prelude::{
-    Color, Commands, Component, Entity, Vec2, Vec3, Command, World, Transform,
+    Color, Commands, Component, Entity, Vec2, Vec3, Command, World, Transform, Quat, error,
 };
 
-use crate::Schedule;
+use crate::{DrawStroke, Schedule, Timeline, TimePoint};
 
-#[derive(Debug, Default, Component, Clone, Copy)]
+#[derive(Debug, Default, Component, Clone, Copy, PartialEq)]
 pub struct Pen {
     pub color: Color,
     pub stroke: Stroke,
+    pub speed: Speed,
 }
 
 impl From<Color> for Pen {
     fn from(color: Color) -> Self {
-        Self { color, stroke: Default::default() }
+        Self { color, stroke: Default::default(), speed: Default::default() }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Stroke {
     Volume(f32),
-    // Ribbon(f32),
-    // Pixels(u32),
+    Ribbon(f32),
+    Pixels(u32),
 }
 
 impl Default for Stroke {
@@ -46,7 +49,32 @@ impl Default for Stroke {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed {
+    /// Units per second
+    pub linear: f32,
+    /// Radians per second
+    pub angular: f32,
+}
+
+impl Speed {
+    pub(crate) fn time_for(&self, distance: f32, angle: f32) -> f32 {
+        let linear_time = if self.linear > 0.0 { distance / self.linear } else { 0.0 };
+        let angular_time = if self.angular > 0.0 { angle / self.angular } else { 0.0 };
+        linear_time.max(angular_time)
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed {
+            linear: 0.2,
+            angular: std::f32::consts::FRAC_PI_2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PenHandle(pub(crate) Entity);
 
 impl PenHandle {
@@ -54,63 +82,210 @@ impl PenHandle {
         &'a self,
         commands: Commands<'w, 's>,
     ) -> PenCommands<'w, 's> {
-        PenCommands { pen: *self, commands }
+        PenCommands::new(*self, commands)
     }
 }
 
 pub struct PenCommands<'w, 's> {
     pub(crate) pen: PenHandle,
     pub(crate) commands: Commands<'w, 's>,
+    pub(crate) duration: Option<f32>,
+    /// Where the next action comes from, when that is not the Rust code that
+    /// calls these methods.
+    pub(crate) source: Option<CallSite>,
 }
 
 impl<'w, 's> PenCommands<'w, 's> {
+    pub(crate) fn new(pen: PenHandle, commands: Commands<'w, 's>) -> Self {
+        PenCommands { pen, commands, duration: None, source: None }
+    }
+
+    /// Make the next action take this many seconds instead of the time that
+    /// the speed of the pen would give it.
+    pub fn with_duration(&mut self, seconds: f32) -> &mut Self {
+        self.duration = Some(seconds);
+        self
+    }
 
+    #[track_caller]
     pub fn draw(&mut self, movement: Movement) {
-        self.commands.queue(PenAction {
-            pen: self.pen.0,
-            movement,
-            draw: true,
-        });
+        self.draw_as(movement, format!("draw({movement:?})"));
     }
 
+    #[track_caller]
     pub fn move_pen(&mut self, movement: Movement) {
-        self.commands.queue(PenAction {
-            pen: self.pen.0,
-            movement,
-            draw: false,
-        });
+        self.move_as(movement, format!("move_pen({movement:?})"));
     }
 
+    #[track_caller]
+    pub fn pen_up(&mut self) {
+        self.queue(PenInstruction::Change(PenChange::Up), "pen_up()".to_owned());
+    }
+
+    #[track_caller]
+    pub fn pen_down(&mut self) {
+        self.queue(PenInstruction::Change(PenChange::Down), "pen_down()".to_owned());
+    }
+
+    #[track_caller]
+    pub fn set_color(&mut self, color: impl Into<Color>) {
+        let color = color.into();
+        let call = format!("set_color({})", color.to_srgba().to_hex());
+        self.queue(PenInstruction::Change(PenChange::Color(color)), call);
+    }
+
+    #[track_caller]
+    pub fn set_stroke(&mut self, stroke: Stroke) {
+        let call = format!("set_stroke({stroke:?})");
+        self.queue(PenInstruction::Change(PenChange::Stroke(stroke)), call);
+    }
+
+    #[track_caller]
     pub fn draw_to(&mut self, point: impl IntoPoint) {
-        self.draw(Movement::ToPoint(point.into_point()));
+        let point = point.into_point();
+        self.draw_as(Movement::ToPoint(point), format!("draw_to({point})"));
     }
 
+    #[track_caller]
     pub fn draw_forward(&mut self, distance: f32) {
-        self.draw(Movement::relative(distance, Direction::Forward));
+        let call = format!("draw_forward({distance})");
+        self.draw_as(Movement::relative(distance, Direction::Forward), call);
     }
 
+    #[track_caller]
     pub fn draw_backward(&mut self, distance: f32) {
-        self.draw(Movement::relative(distance, Direction::Backward));
+        let call = format!("draw_backward({distance})");
+        self.draw_as(Movement::relative(distance, Direction::Backward), call);
     }
 
+    #[track_caller]
     pub fn draw_left(&mut self, distance: f32) {
-        self.draw(Movement::relative(distance, Direction::Left));
+        let call = format!("draw_left({distance})");
+        self.draw_as(Movement::relative(distance, Direction::Left), call);
     }
 
+    #[track_caller]
     pub fn draw_right(&mut self, distance: f32) {
-        self.draw(Movement::relative(distance, Direction::Right));
+        let call = format!("draw_right({distance})");
+        self.draw_as(Movement::relative(distance, Direction::Right), call);
     }
 
+    #[track_caller]
     pub fn draw_up(&mut self, distance: f32) {
-        self.draw(Movement::relative(distance, Direction::Up));
+        let call = format!("draw_up({distance})");
+        self.draw_as(Movement::relative(distance, Direction::Up), call);
     }
 
+    #[track_caller]
     pub fn draw_down(&mut self, distance: f32) {
-        self.draw(Movement::relative(distance, Direction::Down));
+        let call = format!("draw_down({distance})");
+        self.draw_as(Movement::relative(distance, Direction::Down), call);
+    }
+
+    /// Draw along a circular arc that curves to the left for positive degrees
+    /// and to the right for negative degrees.
+    #[track_caller]
+    pub fn draw_arc(&mut self, radius: f32, degrees: f32) {
+        let call = format!("draw_arc({radius}, {degrees})");
+        self.draw_as(Movement::Arc { radius, sweep: degrees.to_radians() }, call);
+    }
+
+    #[track_caller]
+    pub fn draw_circle(&mut self, radius: f32) {
+        let call = format!("draw_circle({radius})");
+        self.draw_as(Movement::Arc { radius, sweep: std::f32::consts::TAU }, call);
+    }
+
+    #[track_caller]
+    pub fn draw_bezier(&mut self, c1: impl IntoPoint, c2: impl IntoPoint, end: impl IntoPoint) {
+        let (c1, c2, end) = (c1.into_point(), c2.into_point(), end.into_point());
+        let call = format!("draw_bezier({c1}, {c2}, {end})");
+        self.draw_as(Movement::CubicBezier { c1, c2, end }, call);
     }
 
+    /// Draw a smooth curve that passes through each point in turn, setting off
+    /// in whichever direction the pen is facing.
+    #[track_caller]
+    pub fn draw_spline<P: IntoPoint>(&mut self, points: impl IntoIterator<Item = P>) {
+        let points: Vec<Vec3> = points.into_iter().map(IntoPoint::into_point).collect();
+        let call = format!(
+            "draw_spline([{}])",
+            points.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "),
+        );
+        let source = self.take_source(call);
+        self.commands.queue(SplineAction {
+            pen: self.pen.0,
+            points,
+            draw: true,
+            duration: self.duration.take(),
+            source,
+        });
+    }
+
+    #[track_caller]
     pub fn move_to(&mut self, point: impl IntoPoint) {
-        self.move_pen(Movement::ToPoint(point.into_point()));
+        let point = point.into_point();
+        self.move_as(Movement::ToPoint(point), format!("move_to({point})"));
+    }
+
+    #[track_caller]
+    pub fn turn(&mut self, degrees: f32, turn: Turn) {
+        self.turn_as(degrees, turn, format!("turn({degrees}, {turn:?})"));
+    }
+
+    #[track_caller]
+    pub fn turn_left(&mut self, degrees: f32) {
+        self.turn_as(degrees, Turn::Left, format!("turn_left({degrees})"));
+    }
+
+    #[track_caller]
+    pub fn turn_right(&mut self, degrees: f32) {
+        self.turn_as(degrees, Turn::Right, format!("turn_right({degrees})"));
+    }
+
+    #[track_caller]
+    pub fn pitch_up(&mut self, degrees: f32) {
+        self.turn_as(degrees, Turn::PitchUp, format!("pitch_up({degrees})"));
+    }
+
+    #[track_caller]
+    pub fn pitch_down(&mut self, degrees: f32) {
+        self.turn_as(degrees, Turn::PitchDown, format!("pitch_down({degrees})"));
+    }
+
+    #[track_caller]
+    pub fn roll_left(&mut self, degrees: f32) {
+        self.turn_as(degrees, Turn::RollLeft, format!("roll_left({degrees})"));
+    }
+
+    #[track_caller]
+    pub fn roll_right(&mut self, degrees: f32) {
+        self.turn_as(degrees, Turn::RollRight, format!("roll_right({degrees})"));
+    }
+
+    #[track_caller]
+    pub fn face_towards(&mut self, point: impl IntoPoint) {
+        let point = point.into_point();
+        self.move_as(Movement::FaceTowards(point), format!("face_towards({point})"));
+    }
+
+    /// Point the pen in a direction within the XY plane, measured in degrees
+    /// counter-clockwise from the X axis.
+    #[track_caller]
+    pub fn set_heading(&mut self, degrees: f32) {
+        let rotation = Quat::from_rotation_z(degrees.to_radians());
+        self.move_as(Movement::ToOrientation(rotation), format!("set_heading({degrees})"));
+    }
+
+    /// Stay still for this many seconds before doing anything else.
+    pub fn wait(&mut self, seconds: f32) {
+        self.commands.queue(Wait { pen: self.pen.0, seconds });
+    }
+
+    /// Hold off on anything else until the other pen has finished everything
+    /// that it has been given so far.
+    pub fn wait_for(&mut self, other: PenHandle) {
+        self.commands.queue(WaitFor { pen: self.pen.0, other: other.0 });
     }
 
     pub fn handle(self) -> PenHandle {
@@ -120,6 +295,54 @@ impl<'w, 's> PenCommands<'w, 's> {
     pub fn unpack(self) -> (PenHandle, Commands<'w, 's>) {
         (self.pen, self.commands)
     }
+
+    #[track_caller]
+    fn draw_as(&mut self, movement: Movement, call: String) {
+        self.queue(PenInstruction::Move { movement, draw: true }, call);
+    }
+
+    #[track_caller]
+    fn move_as(&mut self, movement: Movement, call: String) {
+        self.queue(PenInstruction::Move { movement, draw: false }, call);
+    }
+
+    /// A turn is a rotation-only [`Movement::Relative`], which cannot tell a
+    /// whole revolution apart from no turn at all, so turns are given in
+    /// pieces of at most half a revolution.
+    #[track_caller]
+    fn turn_as(&mut self, degrees: f32, turn: Turn, call: String) {
+        let pieces = if degrees.is_finite() {
+            (degrees.abs() / 180.0).ceil().clamp(1.0, MAX_TURN_PIECES) as usize
+        } else {
+            1
+        };
+        let duration = self.duration.take().map(|seconds| seconds / pieces as f32);
+        let source = self.take_source(call);
+        for _ in 0..pieces {
+            self.source = Some(source.clone());
+            self.duration = duration;
+            self.move_as(Movement::turn(degrees / pieces as f32, turn), String::new());
+        }
+    }
+
+    #[track_caller]
+    fn queue(&mut self, instruction: PenInstruction, call: String) {
+        let source = self.take_source(call);
+        self.commands.queue(PenAction {
+            pen: self.pen.0,
+            instruction,
+            duration: self.duration.take(),
+            source,
+        });
+    }
+
+    #[track_caller]
+    fn take_source(&mut self, call: String) -> CallSite {
+        match self.source.take() {
+            Some(source) => source,
+            None => CallSite::new(call),
+        }
+    }
 }
 
 pub trait IntoPoint {
@@ -142,7 +365,15 @@ impl IntoPoint for Vec2 {
 pub enum Movement {
     ToPoint(Vec3),
     ToPose(Transform),
+    ToOrientation(Quat),
+    FaceTowards(Vec3),
     Relative(Transform),
+    /// Travel along a circle of the given radius, turning by `sweep` radians.
+    /// A positive sweep curves to the left and a negative sweep to the right.
+    Arc { radius: f32, sweep: f32 },
+    /// Travel along a cubic Bézier curve from the current position to `end`,
+    /// shaped by the control points `c1` and `c2`.
+    CubicBezier { c1: Vec3, c2: Vec3, end: Vec3 },
 }
 
 impl Movement {
@@ -162,11 +393,73 @@ impl Movement {
         Movement::Relative(tf)
     }
 
-    fn apply_from(self, tf_initial: &Transform, progress: f32) -> Transform {
+    pub fn turn(degrees: f32, turn: Turn) -> Movement {
+        let radians = degrees.to_radians();
+        let rotation = match turn {
+            Turn::Left => Quat::from_rotation_z(radians),
+            Turn::Right => Quat::from_rotation_z(-radians),
+            Turn::PitchUp => Quat::from_rotation_y(-radians),
+            Turn::PitchDown => Quat::from_rotation_y(radians),
+            Turn::RollLeft => Quat::from_rotation_x(-radians),
+            Turn::RollRight => Quat::from_rotation_x(radians),
+        };
+
+        Movement::Relative(Transform::from_rotation(rotation))
+    }
+
+    pub(crate) fn apply_from(self, tf_initial: &Transform, progress: f32) -> Transform {
         let tf_final = match self {
             Movement::ToPoint(p) => (*tf_initial).with_translation(p),
             Movement::ToPose(pose) => pose,
-            Movement::Relative(relative) => *tf_initial * relative,
+            Movement::ToOrientation(rotation) => (*tf_initial).with_rotation(rotation),
+            Movement::FaceTowards(p) => {
+                let forward = tf_initial.rotation * Vec3::X;
+                let rotation = match (p - tf_initial.translation).try_normalize() {
+                    Some(target) => Quat::from_rotation_arc(forward, target) * tf_initial.rotation,
+                    None => tf_initial.rotation,
+                };
+                (*tf_initial).with_rotation(rotation)
+            }
+            Movement::Relative(relative) => {
+                // Interpolate the relative rotation by its angle so that turns
+                // larger than half a revolution go the way that was asked for.
+                let (axis, angle) = relative.rotation.to_axis_angle();
+                let partial = Transform {
+                    translation: progress * relative.translation,
+                    rotation: Quat::from_axis_angle(axis, progress * angle),
+                    scale: progress * (relative.scale - Vec3::ONE) + Vec3::ONE,
+                };
+                return *tf_initial * partial;
+            }
+            Movement::Arc { radius, sweep } => {
+                let side = if sweep < 0.0 { -radius } else { radius };
+                let center = tf_initial.translation + tf_initial.rotation * (side * Vec3::Y);
+                let rotation = tf_initial.rotation * Quat::from_rotation_z(progress * sweep);
+                let translation = center - rotation * (side * Vec3::Y);
+                return Transform { translation, rotation, scale: tf_initial.scale };
+            }
+            Movement::CubicBezier { c1, c2, end } => {
+                let curve = [tf_initial.translation, c1, c2, end];
+
+                // Carry the orientation along the curve in small steps so the
+                // pen keeps facing along the tangent without flipping over when
+                // the curve turns by more than half a revolution.
+                let mut rotation = tf_initial.rotation;
+                let mut heading = rotation * Vec3::X;
+                for k in 1..=BEZIER_TRANSPORT_STEPS {
+                    let t = progress * k as f32 / BEZIER_TRANSPORT_STEPS as f32;
+                    if let Some(tangent) = bezier_tangent(curve, t) {
+                        rotation = Quat::from_rotation_arc(heading, tangent) * rotation;
+                        heading = tangent;
+                    }
+                }
+
+                return Transform {
+                    translation: bezier_point(curve, progress),
+                    rotation: rotation.normalize(),
+                    scale: tf_initial.scale,
+                };
+            }
         };
 
         let translation = progress * (tf_final.translation - tf_initial.translation) + tf_initial.translation;
@@ -174,6 +467,107 @@ impl Movement {
         let scale = progress * (tf_final.scale - tf_initial.scale) + tf_initial.scale;
         Transform { translation, rotation, scale }
     }
+
+    pub(crate) fn path_length(self, tf_initial: &Transform) -> f32 {
+        match self {
+            Movement::Arc { radius, sweep } => return radius.abs() * sweep.abs(),
+            Movement::CubicBezier { .. } => {
+                return self
+                    .trace(tf_initial, 1.0)
+                    .windows(2)
+                    .map(|w| w[0].translation.distance(w[1].translation))
+                    .sum();
+            }
+            _ => {}
+        }
+
+        let tf_final = self.apply_from(tf_initial, 1.0);
+        (tf_final.translation - tf_initial.translation).length()
+    }
+
+    pub(crate) fn turn_angle(self, tf_initial: &Transform) -> f32 {
+        match self {
+            Movement::Relative(relative) => relative.rotation.to_axis_angle().1,
+            Movement::Arc { sweep, .. } => sweep.abs(),
+            _ => tf_initial.rotation.angle_between(self.apply_from(tf_initial, 1.0).rotation),
+        }
+    }
+
+    pub(crate) fn trace(self, tf_initial: &Transform, progress: f32) -> Vec<Transform> {
+        // Start from a coarse sampling that cannot confuse a large turn with
+        // a small one, then keep splitting wherever the path still bends too
+        // much. Both tolerances are relative, so curves look equally smooth
+        // at any zoom.
+        let turn = progress * self.turn_angle(tf_initial);
+        let min_steps = match self {
+            Movement::CubicBezier { .. } => 4,
+            _ => 1,
+        };
+        let steps = ((turn / std::f32::consts::FRAC_PI_2).ceil() as usize).max(min_steps);
+
+        let mut path = vec![*tf_initial];
+        for i in 1..=steps {
+            let from = (progress * (i - 1) as f32 / steps as f32, *path.last().unwrap());
+            let t = progress * i as f32 / steps as f32;
+            let to = (t, self.apply_from(tf_initial, t));
+            self.refine_trace(tf_initial, from, to, 0, &mut path);
+        }
+
+        path
+    }
+
+    fn refine_trace(
+        self,
+        tf_initial: &Transform,
+        (t_a, pose_a): (f32, Transform),
+        (t_b, pose_b): (f32, Transform),
+        depth: usize,
+        path: &mut Vec<Transform>,
+    ) {
+        if depth < MAX_TRACE_DEPTH {
+            let t_mid = (t_a + t_b) / 2.0;
+            let pose_mid = self.apply_from(tf_initial, t_mid);
+            let chord = pose_b.translation - pose_a.translation;
+            let bow = pose_mid.translation - (pose_a.translation + pose_b.translation) / 2.0;
+            let bends = pose_a.rotation.angle_between(pose_b.rotation) > MAX_TRACE_ANGLE
+                || bow.length() > MAX_TRACE_BOW * chord.length();
+
+            if bends {
+                self.refine_trace(tf_initial, (t_a, pose_a), (t_mid, pose_mid), depth + 1, path);
+                self.refine_trace(tf_initial, (t_mid, pose_mid), (t_b, pose_b), depth + 1, path);
+                return;
+            }
+        }
+
+        path.push(pose_b);
+    }
+}
+
+const MAX_TRACE_DEPTH: usize = 10;
+const MAX_TRACE_ANGLE: f32 = 2.0 * std::f32::consts::PI / 180.0;
+const MAX_TRACE_BOW: f32 = 0.01;
+const BEZIER_TRANSPORT_STEPS: usize = 16;
+
+/// The most pieces that one turn command gets split into. Turns of more than
+/// this many half revolutions lose whole revolutions.
+const MAX_TURN_PIECES: f32 = 64.0;
+
+fn bezier_point([p0, p1, p2, p3]: [Vec3; 4], t: f32) -> Vec3 {
+    let s = 1.0 - t;
+    s * s * s * p0 + 3.0 * s * s * t * p1 + 3.0 * s * t * t * p2 + t * t * t * p3
+}
+
+fn bezier_tangent(curve: [Vec3; 4], t: f32) -> Option<Vec3> {
+    let [p0, p1, p2, p3] = curve;
+    let s = 1.0 - t;
+    let derivative = 3.0 * s * s * (p1 - p0) + 6.0 * s * t * (p2 - p1) + 3.0 * t * t * (p3 - p2);
+    derivative.try_normalize().or_else(|| {
+        // The derivative vanishes where a control point sits on an endpoint,
+        // so look at where the curve is heading from a little further along.
+        let t_near = if t < 0.5 { t + 1e-3 } else { t - 1e-3 };
+        let direction = bezier_point(curve, t_near.max(t)) - bezier_point(curve, t_near.min(t));
+        direction.try_normalize()
+    })
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -187,17 +581,425 @@ pub enum Direction {
 }
 
 #[derive(Debug, Clone, Copy)]
+pub enum Turn {
+    Left,
+    Right,
+    PitchUp,
+    PitchDown,
+    RollLeft,
+    RollRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PenChange {
+    Up,
+    Down,
+    Color(Color),
+    Stroke(Stroke),
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+pub(crate) struct PenState {
+    pub(crate) pen: Pen,
+    pub(crate) down: bool,
+}
+
+impl PenState {
+    pub(crate) fn new(pen: Pen) -> Self {
+        PenState { pen, down: true }
+    }
+
+    pub(crate) fn apply(&mut self, change: PenChange) {
+        match change {
+            PenChange::Up => self.down = false,
+            PenChange::Down => self.down = true,
+            PenChange::Color(color) => self.pen.color = color,
+            PenChange::Stroke(stroke) => self.pen.stroke = stroke,
+        }
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy)]
+pub(crate) struct PenTrack {
+    pub(crate) pose: Transform,
+    pub(crate) state: PenState,
+    /// When the latest action given to the pen will be finished.
+    pub(crate) finish: f32,
+}
+
+impl PenTrack {
+    pub(crate) fn new(pen: Pen) -> Self {
+        PenTrack {
+            pose: Transform::IDENTITY,
+            state: PenState::new(pen),
+            finish: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PenInstruction {
+    Move {
+        movement: Movement,
+        draw: bool,
+    },
+    Change(PenChange),
+}
+
+/// Where in the user's code an action was asked for.
+#[derive(Debug, Clone)]
+pub(crate) struct CallSite {
+    pub(crate) file: Cow<'static, str>,
+    pub(crate) line: u32,
+    /// The call as it was written, e.g. `draw_forward(0.3)`.
+    pub(crate) call: String,
+}
+
+impl CallSite {
+    #[track_caller]
+    pub(crate) fn new(call: String) -> Self {
+        let location = Location::caller();
+        Self {
+            file: Cow::Borrowed(location.file()),
+            line: location.line(),
+            call,
+        }
+    }
+}
+
+impl std::fmt::Display for CallSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{} {}", self.file, self.line, self.call)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct PenAction {
+    pub(crate) pen: Entity,
+    pub(crate) instruction: PenInstruction,
+    pub(crate) duration: Option<f32>,
+    pub(crate) source: CallSite,
+}
+
+impl Command for PenAction {
+    fn apply(mut self, world: &mut World) {
+        let barrier = world.get_resource::<Timeline>().map_or(0.0, |t| t.barrier);
+        let Some(mut track) = world.get_mut::<PenTrack>(self.pen) else {
+            error!("Pen unavailable for action");
+            return;
+        };
+        let initial_pose = track.pose;
+        let stroke_pen = track.state.pen;
+        let (path_length, turn_angle) = match &mut self.instruction {
+            PenInstruction::Move { movement, draw } => {
+                // A lifted pen moves without leaving a stroke behind.
+                *draw &= track.state.down;
+                track.pose = movement.apply_from(&initial_pose, 1.0);
+                (movement.path_length(&initial_pose), movement.turn_angle(&initial_pose))
+            }
+            PenInstruction::Change(change) => {
+                track.state.apply(*change);
+                (0.0, 0.0)
+            }
+        };
+        let planned_duration = track.state.pen.speed.time_for(path_length, turn_angle);
+        let duration = self.duration.unwrap_or(planned_duration);
+
+        // Each pen carries on from where its own track left off, so pens draw
+        // at the same time as each other.
+        let start = track.finish.max(barrier);
+        let finish = start + duration.max(0.0);
+        track.finish = finish;
+
+        world.get_resource_or_init::<Timeline>().push(TimePoint {
+            start,
+            finish,
+            initial_pose,
+            path_length,
+            turn_angle,
+        });
+
+        let mut schedule = world.get_resource_or_init::<Schedule>();
+        let action = schedule.actions.len();
+        let (pen, instruction) = (self.pen, self.instruction);
+        schedule.actions.push(self);
+
+        if let PenInstruction::Move { movement, draw: true } = instruction {
+            DrawStroke {
+                pen,
+                action,
+                path: movement.trace(&initial_pose, 0.0),
+                color: stroke_pen.color,
+                stroke: stroke_pen.stroke,
+            }.apply(world);
+        }
+    }
+}
+
+pub(crate) struct Wait {
     pen: Entity,
-    movement: Movement,
+    seconds: f32,
+}
+
+impl Command for Wait {
+    fn apply(self, world: &mut World) {
+        let barrier = world.get_resource::<Timeline>().map_or(0.0, |t| t.barrier);
+        let Some(mut track) = world.get_mut::<PenTrack>(self.pen) else {
+            error!("Pen unavailable for wait");
+            return;
+        };
+
+        track.finish = track.finish.max(barrier) + self.seconds.max(0.0);
+        let finish = track.finish;
+        world.get_resource_or_init::<Timeline>().extend_to(finish);
+    }
+}
+
+pub(crate) struct WaitFor {
+    pen: Entity,
+    other: Entity,
+}
+
+impl Command for WaitFor {
+    fn apply(self, world: &mut World) {
+        let Some(other) = world.get::<PenTrack>(self.other) else {
+            error!("Pen unavailable to wait for");
+            return;
+        };
+
+        let other_finish = other.finish;
+        let Some(mut track) = world.get_mut::<PenTrack>(self.pen) else {
+            error!("Pen unavailable for wait");
+            return;
+        };
+
+        track.finish = track.finish.max(other_finish);
+    }
+}
+
+/// Expands a Catmull-Rom spline into Bézier movements once the pose that the
+/// spline starts from is known.
+pub(crate) struct SplineAction {
+    pen: Entity,
+    points: Vec<Vec3>,
     draw: bool,
+    duration: Option<f32>,
+    source: CallSite,
 }
 
-impl Command for PenAction {
+impl Command for SplineAction {
     fn apply(self, world: &mut World) {
-        world.get_resource_or_init::<Schedule>().actions.push(self);
-        let mut tf_initial = world.get_mut::<Transform>(self.pen).unwrap();
-        let tf_final = self.movement.apply_from(&*tf_initial, 1.0);
-        *tf_initial = tf_final;
+        let Some(track) = world.get::<PenTrack>(self.pen) else {
+            error!("Pen unavailable for spline");
+            return;
+        };
+
+        let start = track.pose;
+        let knots: Vec<Vec3> = [start.translation].into_iter().chain(self.points).collect();
+        let n = knots.len() - 1;
+        if n == 0 {
+            return;
+        }
+
+        let tangents: Vec<Vec3> = (0..=n)
+            .map(|i| {
+                if i == 0 {
+                    (start.rotation * Vec3::X) * knots[0].distance(knots[1])
+                } else if i == n {
+                    knots[n] - knots[n - 1]
+                } else {
+                    (knots[i + 1] - knots[i - 1]) / 2.0
+                }
+            })
+            .collect();
+
+        let movements: Vec<Movement> = (0..n)
+            .map(|i| Movement::CubicBezier {
+                c1: knots[i] + tangents[i] / 3.0,
+                c2: knots[i + 1] - tangents[i + 1] / 3.0,
+                end: knots[i + 1],
+            })
+            .collect();
+
+        // Share any requested duration between the segments by their length.
+        let lengths: Vec<f32> = movements
+            .iter()
+            .zip(&knots)
+            .map(|(movement, knot)| movement.path_length(&Transform::from_translation(*knot)))
+            .collect();
+        let total_length: f32 = lengths.iter().sum();
+
+        for (movement, length) in movements.into_iter().zip(lengths) {
+            let duration = self.duration.map(|d| {
+                if total_length > 0.0 { d * length / total_length } else { d / n as f32 }
+            });
+
+            PenAction {
+                pen: self.pen,
+                instruction: PenInstruction::Move { movement, draw: self.draw },
+                duration,
+                source: self.source.clone(),
+            }.apply(world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sketch;
+    use std::f32::consts::PI;
+
+    fn heading(tf: Transform) -> Vec3 {
+        tf.rotation * Vec3::X
+    }
+
+    #[test]
+    fn full_turns_yaw_the_whole_way_around() {
+        let mut sketch = Sketch::headless();
+        let pen = {
+            let mut pen = sketch.spawn_pen(Color::WHITE);
+            pen.turn_left(360.0);
+            pen.turn_right(720.0);
+            pen.handle()
+        };
+        sketch.app.world_mut().flush();
+
+        // Every piece of each turn is a rotation-only relative movement of at
+        // most half a revolution.
+        let schedule = sketch.app.world().resource::<Schedule>();
+        assert_eq!(schedule.actions.len(), 6);
+        for action in &schedule.actions {
+            let instruction = action.instruction;
+            let PenInstruction::Move { movement: Movement::Relative(tf), .. } = instruction else {
+                panic!("unexpected {instruction:?}");
+            };
+            assert_eq!(tf.translation, Vec3::ZERO);
+            assert!((tf.rotation.to_axis_angle().1 - PI).abs() < 1e-4);
+        }
+
+        // Turning takes the same time for each radian, so the left turn takes
+        // the first third of the sketch.
+        let duration = sketch.app.world().resource::<Timeline>().duration();
+        let mut heading_at = |fraction: f32| {
+            let pose = sketch.evaluate_at(fraction * duration).pen(pen).unwrap().pose;
+            // The pen never leaves the plane that it is turning in.
+            assert!((pose.rotation * Vec3::Z).distance(Vec3::Z) < 1e-5);
+            heading(pose)
+        };
+        assert!(heading_at(1.0 / 12.0).distance(Vec3::Y) < 1e-5);
+        assert!(heading_at(1.0 / 6.0).distance(Vec3::NEG_X) < 1e-5);
+        assert!(heading_at(1.0 / 3.0 + 1.0 / 12.0).distance(Vec3::NEG_Y) < 1e-5);
+        assert!(heading_at(1.0 / 3.0 + 5.0 / 12.0).distance(Vec3::NEG_Y) < 1e-5);
+        assert!(heading_at(1.0).distance(Vec3::X) < 1e-5);
+    }
+
+    /// Check that each step of a trace turns by no more than the tolerance
+    /// and that it starts and ends where the movement does.
+    fn assert_smooth_trace(movement: Movement, start: &Transform) -> Vec<Transform> {
+        let path = movement.trace(start, 1.0);
+        let end = movement.apply_from(start, 1.0);
+        assert!(path.first().unwrap().translation.distance(start.translation) < 1e-6);
+        assert!(path.last().unwrap().translation.distance(end.translation) < 1e-5);
+        for w in path.windows(2) {
+            assert!(w[0].rotation.angle_between(w[1].rotation) <= MAX_TRACE_ANGLE + 1e-3);
+        }
+        path
+    }
+
+    #[test]
+    fn arcs_end_where_the_circle_says() {
+        let start = Transform::from_xyz(1.0, 2.0, 0.0).with_rotation(Quat::from_rotation_z(0.5));
+        for (radius, sweep) in [(2.0, PI / 2.0), (2.0, -PI / 2.0), (0.5, 1.5 * PI)] {
+            let arc = Movement::Arc { radius, sweep };
+            let left = start.rotation * Vec3::Y;
+            let center = start.translation + sweep.signum() * radius * left;
+            let turn = Quat::from_rotation_z(sweep);
+
+            let end = arc.apply_from(&start, 1.0);
+            let expected = center + turn * (start.translation - center);
+            assert!(end.translation.distance(expected) < 1e-5);
+            assert!(heading(end).distance(turn * heading(start)) < 1e-5);
+            assert!((arc.path_length(&start) - radius * sweep.abs()).abs() < 1e-5);
+            assert!((arc.turn_angle(&start) - sweep.abs()).abs() < 1e-5);
+
+            let path = assert_smooth_trace(arc, &start);
+            assert!(path.len() as f32 >= sweep.abs() / MAX_TRACE_ANGLE);
+            for pose in &path {
+                assert!((pose.translation.distance(center) - radius).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn beziers_end_on_their_endpoint_facing_along_the_curve() {
+        // A U-turn, which takes the pen through half a revolution.
+        let (c1, c2, end) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::Y);
+        let bezier = Movement::CubicBezier { c1, c2, end };
+        let start = Transform::IDENTITY;
+
+        let last = bezier.apply_from(&start, 1.0);
+        assert_eq!(last.translation, end);
+        assert!(heading(last).distance((end - c2).normalize()) < 1e-4);
+        assert!((bezier.turn_angle(&start) - PI).abs() < 1e-3);
+
+        // Turning in the plane of the curve never flips the pen over.
+        let path = assert_smooth_trace(bezier, &start);
+        for pose in &path {
+            assert!((pose.rotation * Vec3::Z).distance(Vec3::Z) < 1e-4);
+        }
+
+        let curve = [start.translation, c1, c2, end];
+        let samples = 10_000;
+        let length: f32 = (1..=samples)
+            .map(|k| {
+                let (a, b) = ((k - 1) as f32 / samples as f32, k as f32 / samples as f32);
+                bezier_point(curve, a).distance(bezier_point(curve, b))
+            })
+            .sum();
+        assert!((bezier.path_length(&start) - length).abs() < 1e-3 * length);
+    }
+
+    #[test]
+    fn splines_pass_through_every_point_without_kinks() {
+        let mut sketch = Sketch::headless();
+        let points = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let pen = {
+            let mut pen = sketch.spawn_pen(Color::WHITE);
+            pen.with_duration(3.0).draw_spline(points);
+            pen.handle()
+        };
+        sketch.app.world_mut().flush();
+
+        let world = sketch.app.world();
+        let schedule = world.resource::<Schedule>();
+        let timeline = world.resource::<Timeline>();
+        assert_eq!(schedule.actions.len(), points.len());
+        assert!((timeline.duration() - 3.0).abs() < 1e-4);
+
+        // The spline sets off the way the pen was facing.
+        let (mut previous_end, mut previous_heading) = (Vec3::ZERO, Vec3::X);
+        let pieces = schedule.actions.iter().zip(&timeline.time_points).zip(points);
+        for ((action, time_point), point) in pieces {
+            let PenInstruction::Move { movement, .. } = action.instruction else {
+                panic!("unexpected {:?}", action.instruction);
+            };
+            let Movement::CubicBezier { c1, c2, end } = movement else {
+                panic!("unexpected {movement:?}");
+            };
+            assert_eq!(end, point);
+
+            // Each piece starts where the last one ended, heading the same way.
+            let start = time_point.initial_pose;
+            assert!(start.translation.distance(previous_end) < 1e-6);
+            assert!((c1 - start.translation).normalize().distance(previous_heading) < 1e-5);
+            assert!(heading(start).distance(previous_heading) < 1e-4);
+
+            previous_end = movement.apply_from(&start, 1.0).translation;
+            previous_heading = (end - c2).normalize();
+        }
+
+        let drawing = sketch.evaluate();
+        assert!(drawing.pen(pen).unwrap().pose.translation.distance(points[2]) < 1e-6);
     }
 }
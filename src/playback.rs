@@ -0,0 +1,252 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use bevy::{
+    prelude::{
+        AppExit, DetectChangesMut, Entity, EventWriter, Query, Res, ResMut, Resource, Time,
+        Transform, Visibility,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    Pen, PenInstruction, PenState, Schedule, StrokeSegment, Timeline,
+};
+
+#[derive(Resource, Debug, Clone)]
+pub(crate) struct Playback {
+    pub(crate) time: f32,
+    pub(crate) paused: bool,
+    /// How many seconds of the sketch play back for each second of real time.
+    pub(crate) speed: f32,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            paused: false,
+            speed: 1.0,
+        }
+    }
+}
+
+/// Times closer together than this count as the same moment when stepping.
+const STEP_TOLERANCE: f32 = 1e-4;
+
+impl Playback {
+    /// Pause at the next moment that any action starts or finishes.
+    pub(crate) fn step_forward(&mut self, timeline: &Timeline) {
+        self.paused = true;
+        self.time = timeline
+            .time_points
+            .iter()
+            .flat_map(|t| [t.start, t.finish])
+            .filter(|t| *t > self.time + STEP_TOLERANCE)
+            .min_by(f32::total_cmp)
+            .unwrap_or(timeline.duration());
+    }
+
+    /// Pause at the previous moment that any action starts or finishes.
+    pub(crate) fn step_back(&mut self, timeline: &Timeline) {
+        self.paused = true;
+        self.time = timeline
+            .time_points
+            .iter()
+            .flat_map(|t| [t.start, t.finish])
+            .filter(|t| *t < self.time - STEP_TOLERANCE)
+            .max_by(f32::total_cmp)
+            .unwrap_or(0.0);
+    }
+
+    pub(crate) fn restart(&mut self) {
+        self.time = 0.0;
+        self.paused = false;
+    }
+}
+
+pub(crate) fn play_schedule(
+    time: Res<Time>,
+    mut playback: ResMut<Playback>,
+    schedule: Res<Schedule>,
+    timeline: Res<Timeline>,
+    mut pens: Query<(Entity, &Pen, &mut Transform, &mut PenState)>,
+    mut strokes: Query<(&mut StrokeSegment, &mut Visibility)>,
+) {
+    if !playback.paused {
+        playback.time += playback.speed * time.delta_secs();
+    }
+    // Stay at the end once it is reached so that stepping back starts from
+    // there and the sketch still counts as finished.
+    if playback.time > timeline.duration() {
+        playback.time = timeline.duration();
+    }
+    let now = playback.time;
+
+    let mut replay = schedule.replay(&timeline, now, pens.iter().map(|(e, pen, ..)| (e, *pen)));
+    // Pens whose first action has not started yet wait where they were
+    // spawned, so that scrubbing back puts them there too.
+    let mut poses: HashMap<Entity, Transform> = pens
+        .iter()
+        .map(|(entity, ..)| (entity, Transform::IDENTITY))
+        .collect();
+    for step in &mut replay {
+        if let PenInstruction::Move { movement, .. } = step.action.instruction {
+            let pose = movement.apply_from(&step.time_point.initial_pose, step.progress);
+            poses.insert(step.action.pen, pose);
+        }
+    }
+    let states = replay.into_states();
+
+    for (entity, _, mut tf, mut current_state) in &mut pens {
+        if let Some(pose) = poses.get(&entity) {
+            tf.set_if_neq(*pose);
+        }
+
+        if let Some(state) = states.get(&entity) {
+            current_state.set_if_neq(*state);
+        }
+    }
+
+    for (mut segment, mut visibility) in &mut strokes {
+        let (Some(action), Some(time_point)) = (
+            schedule.actions.get(segment.action),
+            timeline.time_points.get(segment.action),
+        ) else {
+            continue;
+        };
+
+        let progress = time_point.progress(now);
+        if progress == segment.progress {
+            continue;
+        }
+        segment.progress = progress;
+
+        if progress <= 0.0 {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        *visibility = Visibility::Inherited;
+
+        if let PenInstruction::Move { movement, .. } = action.instruction {
+            segment.path = movement.trace(&time_point.initial_pose, progress);
+        }
+    }
+}
+
+pub(crate) fn exit_when_finished(
+    playback: Res<Playback>,
+    timeline: Res<Timeline>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if playback.time >= timeline.duration() {
+        exit.send(AppExit::Success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        prelude::{Color, Vec3},
+        time::TimeUpdateStrategy,
+    };
+
+    use super::*;
+    use crate::{PenHandle, Sketch};
+
+    fn pose(sketch: &Sketch, pen: PenHandle) -> Transform {
+        *sketch.app.world().get::<Transform>(pen.0).unwrap()
+    }
+
+    fn play_at(sketch: &mut Sketch, time: impl FnOnce(&mut Playback, &Timeline)) {
+        let world = sketch.app.world_mut();
+        let timeline = world.resource::<Timeline>().clone();
+        time(&mut world.resource_mut::<Playback>(), &timeline);
+        sketch.app.update();
+    }
+
+    #[test]
+    fn playback_stops_at_the_end_of_the_timeline() {
+        let mut sketch = Sketch::headless();
+        sketch.app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs(1)));
+        sketch.spawn_pen(Color::WHITE).draw_forward(0.2);
+        sketch.app.world_mut().flush();
+
+        // Virtual time moves at most a quarter of a second per update, so
+        // this plays well past the end.
+        for _ in 0..10 {
+            sketch.app.update();
+        }
+        let duration = sketch.app.world().resource::<Timeline>().duration();
+        assert!(duration > 0.0);
+        assert_eq!(sketch.app.world().resource::<Playback>().time, duration);
+
+        // One step back lands on the start of the only action, not somewhere
+        // past the end.
+        play_at(&mut sketch, |playback, timeline| playback.step_back(timeline));
+        assert_eq!(sketch.app.world().resource::<Playback>().time, 0.0);
+    }
+
+    #[test]
+    fn restart_puts_every_pen_back_where_it_was_spawned() {
+        let mut sketch = Sketch::headless();
+        // Playback only moves when the test moves it.
+        sketch.app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+
+        let first = {
+            let mut pen = sketch.spawn_pen(Color::WHITE);
+            pen.draw_forward(0.2);
+            pen.turn_left(90.0);
+            pen.handle()
+        };
+        let second = {
+            let mut pen = sketch.spawn_pen(Color::WHITE);
+            pen.wait(1.0);
+            pen.draw_right(0.1);
+            pen.handle()
+        };
+        sketch.barrier();
+        sketch.pen(second).draw_forward(0.1);
+        sketch.app.world_mut().flush();
+
+        play_at(&mut sketch, |playback, timeline| playback.time = timeline.duration());
+        for pen in [first, second] {
+            assert_ne!(pose(&sketch, pen), Transform::IDENTITY);
+        }
+
+        play_at(&mut sketch, |playback, _| playback.restart());
+        for pen in [first, second] {
+            assert_eq!(pose(&sketch, pen), Transform::IDENTITY);
+        }
+
+        // Scrubbing to before the second pen has started leaves it waiting
+        // where it began while the first pen is on its way.
+        play_at(&mut sketch, |playback, timeline| playback.time = timeline.duration());
+        play_at(&mut sketch, |playback, _| playback.time = 0.5);
+        assert!((pose(&sketch, first).translation.x - 0.1).abs() < 1e-5);
+        assert_eq!(pose(&sketch, second), Transform::IDENTITY);
+
+        // Stepping back from the end lands on the last pen's action boundaries
+        // without leaving anything at its final pose.
+        play_at(&mut sketch, |playback, timeline| playback.time = timeline.duration());
+        play_at(&mut sketch, |playback, timeline| playback.step_back(timeline));
+        let second_pose = pose(&sketch, second);
+        assert!(second_pose.translation.distance(Vec3::new(0.0, -0.1, 0.0)) < 1e-5);
+    }
+}
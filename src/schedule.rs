@@ -15,17 +15,179 @@
  *
 */
 
-use bevy::prelude::Resource;
+use std::{iter::Zip, slice};
 
-use crate::PenAction;
+use bevy::{
+    prelude::{Command, Entity, Resource, Transform, With, World},
+    utils::HashMap,
+};
+
+use crate::{Pen, PenAction, PenInstruction, PenState, PenTrack, Playback, StrokeSegment};
 
 #[derive(Resource, Default, Debug, Clone)]
 pub struct Schedule {
     pub(crate) actions: Vec<PenAction>,
 }
 
+impl Schedule {
+    /// Go through every action that has started by `time`, keeping track of
+    /// the state of each of the `pens`. Actions of any other pens are skipped.
+    pub(crate) fn replay<'a>(
+        &'a self,
+        timeline: &'a Timeline,
+        time: f32,
+        pens: impl IntoIterator<Item = (Entity, Pen)>,
+    ) -> Replay<'a> {
+        Replay {
+            steps: self.actions.iter().zip(&timeline.time_points),
+            time,
+            states: pens.into_iter().map(|(entity, pen)| (entity, PenState::new(pen))).collect(),
+        }
+    }
+}
+
+/// An action that has started, along with how far along it is and the state
+/// that its pen is in while carrying it out.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ReplayStep<'a> {
+    pub(crate) action: &'a PenAction,
+    pub(crate) time_point: &'a TimePoint,
+    pub(crate) progress: f32,
+    pub(crate) state: PenState,
+}
+
+/// Steps through the schedule in the order that the actions were given. Each
+/// pen has its actions given in the order they happen, so the last step for a
+/// pen always has its latest pose and state.
+pub(crate) struct Replay<'a> {
+    steps: Zip<slice::Iter<'a, PenAction>, slice::Iter<'a, TimePoint>>,
+    time: f32,
+    states: HashMap<Entity, PenState>,
+}
+
+impl Replay<'_> {
+    /// The state of every pen once all the steps so far have been taken.
+    pub(crate) fn into_states(self) -> HashMap<Entity, PenState> {
+        self.states
+    }
+}
+
+impl<'a> Iterator for Replay<'a> {
+    type Item = ReplayStep<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (action, time_point) = self.steps.next()?;
+            if self.time < time_point.start {
+                continue;
+            }
+
+            let Some(state) = self.states.get_mut(&action.pen) else {
+                continue;
+            };
+
+            if let PenInstruction::Change(change) = action.instruction {
+                state.apply(change);
+            }
+
+            return Some(ReplayStep {
+                action,
+                time_point,
+                progress: time_point.progress(self.time),
+                state: *state,
+            });
+        }
+    }
+}
+
 #[derive(Resource, Default, Debug, Clone)]
 pub struct Timeline {
-    pub(crate) time_points: Vec<f32>,
+    /// Each pen has its own track, so these are ordered by when they were
+    /// given rather than by when they start.
+    pub(crate) time_points: Vec<TimePoint>,
+    /// No action may start before this time.
+    pub(crate) barrier: f32,
+    end: f32,
+}
+
+impl Timeline {
+    pub(crate) fn duration(&self) -> f32 {
+        self.end
+    }
+
+    pub(crate) fn push(&mut self, time_point: TimePoint) {
+        self.extend_to(time_point.finish);
+        self.time_points.push(time_point);
+    }
+
+    /// Make sure the sketch lasts at least until `time`.
+    pub(crate) fn extend_to(&mut self, time: f32) {
+        self.end = self.end.max(time);
+    }
+}
+
+/// Hold back every action given after this until all the actions given
+/// before it have finished.
+pub(crate) struct Barrier;
+
+impl Command for Barrier {
+    fn apply(self, world: &mut World) {
+        let mut timeline = world.get_resource_or_init::<Timeline>();
+        timeline.barrier = timeline.duration();
+    }
+}
+
+/// Forget everything that the pens have been given and wipe away all their
+/// strokes, putting every pen back where it started.
+pub(crate) struct ClearSketch;
+
+impl Command for ClearSketch {
+    fn apply(self, world: &mut World) {
+        let strokes: Vec<Entity> = world
+            .query_filtered::<Entity, With<StrokeSegment>>()
+            .iter(world)
+            .collect();
+        for stroke in strokes {
+            world.despawn(stroke);
+        }
+
+        let mut pens = world.query::<(&Pen, &mut PenTrack, &mut PenState, &mut Transform)>();
+        for (pen, mut track, mut state, mut tf) in pens.iter_mut(world) {
+            *track = PenTrack::new(*pen);
+            *state = PenState::new(*pen);
+            *tf = Transform::IDENTITY;
+        }
+
+        world.insert_resource(Schedule::default());
+        world.insert_resource(Timeline::default());
+        if let Some(mut playback) = world.get_resource_mut::<Playback>() {
+            playback.restart();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TimePoint {
+    pub(crate) start: f32,
+    pub(crate) finish: f32,
+    pub(crate) initial_pose: Transform,
+    /// How far the action carries the pen, measured once when it is planned
+    /// because curves are costly to measure.
+    pub(crate) path_length: f32,
+    /// How many radians the action turns the pen through.
+    pub(crate) turn_angle: f32,
 }
 
+impl TimePoint {
+    pub(crate) fn progress(&self, time: f32) -> f32 {
+        if time < self.start {
+            return 0.0;
+        }
+
+        if self.finish <= self.start {
+            return 1.0;
+        }
+
+        ((time - self.start) / (self.finish - self.start)).min(1.0)
+    }
+}
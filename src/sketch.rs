@@ -15,13 +15,33 @@
  *
 */
 
-use bevy::prelude::{
-    App, AmbientLight, DefaultPlugins, Commands, Resource, Entity, Camera3d, Transform,
-    Vec3,
+use std::time::Duration;
+
+use bevy::{
+    app::{Plugins, ScheduleRunnerPlugin},
+    asset::RenderAssetUsages,
+    prelude::{
+        App, AmbientLight, DefaultPlugins, MinimalPlugins, Commands, Resource, Entity, Camera3d,
+        Camera, Image, Assets, Transform, Vec3, Update, PostUpdate, IntoSystemConfigs,
+        TransformSystem, PluginGroup,
+    },
+    render::{
+        camera::RenderTarget,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+    ui::UiSystem,
+    window::{ExitCondition, WindowPlugin},
+    winit::WinitPlugin,
 };
 pub use bevy::prelude::{AppExit, Color};
 
-use crate::{AddCrab, Crab, CrabName, PenCommands, Pen, PenHandle, Schedule, Timeline};
+use crate::{
+    animate_crabs, control_camera, control_playback, exit_when_finished, play_schedule,
+    spawn_source_overlay, spawn_transport, update_crab_avatars, update_crab_labels,
+    update_source_overlay, update_stroke_meshes, update_transport, AddCrab, Barrier,
+    CameraControls, ClearSketch, Crab, CrabLabels, CrabName, Drawing, Pen, PenCommands, PenHandle,
+    PenState, PenTrack, Playback, Schedule, Timeline,
+};
 
 pub struct Sketch {
     pub app: App,
@@ -29,6 +49,57 @@ pub struct Sketch {
 
 impl Sketch {
     pub fn new() -> Self {
+        let mut sketch = Self::rendered(DefaultPlugins);
+        let world = sketch.app.world_mut();
+        let main_camera = world.resource::<MainCamera>().entity;
+        spawn_transport(world, main_camera);
+        spawn_source_overlay(world, main_camera);
+        sketch
+    }
+
+    /// Make a sketch that renders into an image of the given size instead of
+    /// a window. Use [`Sketch::capture`] to save what it draws.
+    pub fn offscreen(width: u32, height: u32) -> Self {
+        let mut sketch = Self::rendered((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)),
+        ));
+
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let mut image = Image::new_fill(
+            size,
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Bgra8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_DST
+            | TextureUsages::COPY_SRC
+            | TextureUsages::RENDER_ATTACHMENT;
+
+        let world = sketch.app.world_mut();
+        let image = world.resource_mut::<Assets<Image>>().add(image);
+        let main_camera = world.resource::<MainCamera>().entity;
+        world.entity_mut(main_camera).insert(Camera {
+            target: RenderTarget::Image(image),
+            ..Default::default()
+        });
+
+        sketch
+    }
+
+    fn rendered<M>(plugins: impl Plugins<M>) -> Self {
         let mut app = App::new();
         app
             .insert_resource(AmbientLight {
@@ -38,28 +109,100 @@ impl Sketch {
             })
             .init_resource::<Schedule>()
             .init_resource::<Timeline>()
-            .add_plugins(DefaultPlugins);
+            .init_resource::<Playback>()
+            .init_resource::<CrabLabels>()
+            .add_plugins(plugins)
+            .add_systems(
+                Update,
+                (
+                    control_playback,
+                    play_schedule,
+                    update_crab_avatars,
+                    animate_crabs,
+                    update_transport,
+                    update_source_overlay,
+                ).chain(),
+            )
+            .add_systems(Update, control_camera)
+            .add_systems(
+                PostUpdate,
+                (
+                    update_stroke_meshes.after(TransformSystem::TransformPropagate),
+                    update_crab_labels.before(UiSystem::Layout),
+                ),
+            );
 
         let main_camera = app.world_mut().spawn((
             Camera3d::default(),
             Transform::from_xyz(0., 0., 1.).looking_at(Vec3::ZERO, Vec3::Y),
+            CameraControls::new(Vec3::ZERO, 1.0),
         )).id();
         app.world_mut().insert_resource(MainCamera { entity: main_camera });
 
         Sketch { app }
     }
 
+    /// Make a sketch that has no window or rendering. Use [`Sketch::evaluate`]
+    /// to find out what it draws, or [`Sketch::run`] to play it back in real
+    /// time and exit once it is finished.
+    pub fn headless() -> Self {
+        let mut app = App::new();
+        app
+            .init_resource::<Schedule>()
+            .init_resource::<Timeline>()
+            .init_resource::<Playback>()
+            .add_plugins(MinimalPlugins)
+            .add_systems(Update, (play_schedule, exit_when_finished).chain());
+
+        Sketch { app }
+    }
+
     pub fn spawn_pen(&mut self, pen: impl Into<Settings>) -> PenCommands {
         let settings: Settings = pen.into();
         let mut commands = self.app.world_mut().commands();
         let pen = settings.spawn_pen(&mut commands);
-        PenCommands { pen, commands }
+        PenCommands::new(pen, commands)
+    }
+
+    /// Give more commands to a pen that was spawned earlier.
+    pub fn pen(&mut self, pen: PenHandle) -> PenCommands<'_, '_> {
+        pen.command(self.app.world_mut().commands())
+    }
+
+    /// Make every pen finish what it has been given so far before any pen
+    /// starts on what it is given next.
+    pub fn barrier(&mut self) {
+        self.app.world_mut().commands().queue(Barrier);
+    }
+
+    /// Wipe the sketch clean so that the pens can start over from where they
+    /// were spawned.
+    pub fn clear(&mut self) {
+        self.app.world_mut().commands().queue(ClearSketch);
+    }
+
+    /// Show or hide the name labels of every crab while the sketch plays.
+    /// Labels are shown unless this says otherwise.
+    pub fn show_labels(&mut self, show: bool) {
+        self.app.world_mut().insert_resource(CrabLabels { visible: show });
     }
 
     pub fn run(&mut self) -> AppExit {
         self.app.world_mut().flush();
         self.app.run()
     }
+
+    /// Get the finished drawing without playing the sketch back.
+    pub fn evaluate(&mut self) -> Drawing {
+        self.evaluate_at(f32::INFINITY)
+    }
+
+    /// Get the drawing as it would look after `time` seconds of playback.
+    pub fn evaluate_at(&mut self, time: f32) -> Drawing {
+        let world = self.app.world_mut();
+        world.flush();
+        Drawing::at(world, time)
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -70,7 +213,11 @@ pub struct Settings {
 
 impl Settings {
     pub fn spawn_pen(self, commands: &mut Commands) -> PenHandle {
-        let pen = commands.spawn(self.pen).id();
+        let pen = commands.spawn((
+            self.pen,
+            PenTrack::new(self.pen),
+            PenState::new(self.pen),
+        )).id();
         commands.queue(AddCrab { pen, crab: self.crab });
 
         PenHandle(pen)
@@ -87,6 +234,6 @@ impl<T: Into<Pen>> From<T> for Settings {
 }
 
 #[derive(Resource)]
-struct MainCamera {
-    entity: Entity,
+pub(crate) struct MainCamera {
+    pub(crate) entity: Entity,
 }
@@ -0,0 +1,91 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use bevy::prelude::{
+    BackgroundColor, Color, Component, Entity, Node, PositionType, Query, Res, TargetCamera,
+    Text, TextFont, UiRect, Val, With, World,
+};
+
+use crate::{CrabName, Playback, Schedule, Timeline};
+
+const OVERLAY_FONT_SIZE: f32 = 14.0;
+const OVERLAY_BACKGROUND: Color = Color::srgba(0.0, 0.0, 0.0, 0.6);
+
+/// Text that shows which line of the sketch each pen is carrying out.
+#[derive(Debug, Component)]
+pub(crate) struct SourceOverlay;
+
+pub(crate) fn spawn_source_overlay(world: &mut World, camera: Entity) {
+    world.spawn((
+        SourceOverlay,
+        Text::default(),
+        TextFont {
+            font_size: OVERLAY_FONT_SIZE,
+            ..Default::default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            top: Val::Px(8.0),
+            padding: UiRect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        BackgroundColor(OVERLAY_BACKGROUND),
+        TargetCamera(camera),
+    ));
+}
+
+pub(crate) fn update_source_overlay(
+    playback: Res<Playback>,
+    schedule: Res<Schedule>,
+    timeline: Res<Timeline>,
+    names: Query<&CrabName>,
+    mut overlays: Query<&mut Text, With<SourceOverlay>>,
+) {
+    // For each pen, the most recent action that playback has reached. Pens
+    // are listed in the order that they first appear in the schedule.
+    let mut current: Vec<(Entity, usize)> = Vec::new();
+    let reached = schedule.actions.iter().zip(&timeline.time_points).enumerate();
+    for (index, (action, time)) in reached {
+        if time.start > playback.time {
+            continue;
+        }
+
+        match current.iter_mut().find(|(pen, _)| *pen == action.pen) {
+            Some((_, latest)) => *latest = index,
+            None => current.push((action.pen, index)),
+        }
+    }
+
+    let lines: Vec<String> = current
+        .into_iter()
+        .map(|(pen, index)| {
+            let source = &schedule.actions[index].source;
+            match names.get(pen) {
+                Ok(name) if !name.0.is_empty() => format!("{}: {source}", name.0),
+                _ => source.to_string(),
+            }
+        })
+        .collect();
+    let content = lines.join("\n");
+
+    for mut text in &mut overlays {
+        if text.0 != content {
+            text.0 = content.clone();
+        }
+    }
+}
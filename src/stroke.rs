@@ -0,0 +1,246 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use bevy::{
+    prelude::{
+        Assets, Camera, Color, Command, Component, Entity, GlobalTransform, Mesh, Mesh3d,
+        MeshMaterial3d, Query, Ref, Res, ResMut, StandardMaterial, Transform, Vec2, Vec3,
+        Visibility, World, DetectChanges, error,
+    },
+    math::Affine3A,
+};
+
+use crate::{
+    crab::shapes::{make_flat_disk, make_ribbon, make_sphere, make_tube, Circle, MeshBuffer},
+    MainCamera, Stroke,
+};
+
+#[derive(Debug, Component)]
+pub(crate) struct StrokeSegment {
+    pub(crate) action: usize,
+    pub(crate) stroke: Stroke,
+    pub(crate) progress: f32,
+    pub(crate) path: Vec<Transform>,
+}
+
+pub(crate) struct DrawStroke {
+    pub(crate) pen: Entity,
+    pub(crate) action: usize,
+    pub(crate) path: Vec<Transform>,
+    pub(crate) color: Color,
+    pub(crate) stroke: Stroke,
+}
+
+impl Command for DrawStroke {
+    fn apply(self, world: &mut World) {
+        if world.get_entity(self.pen).is_err() {
+            error!("Pen unavailable for stroke");
+            return;
+        }
+
+        if !world.contains_resource::<Assets<Mesh>>() {
+            // There is nothing to render into for a headless sketch.
+            return;
+        }
+
+        let mesh: Mesh = make_stroke_mesh(self.stroke, &self.path).into();
+        let mesh_handle = world.resource_mut::<Assets<Mesh>>().add(mesh);
+        let material = match self.stroke {
+            // Screen-space lines are flat diagram lines, so they skip lighting
+            // and can be seen from either side.
+            Stroke::Pixels(_) => StandardMaterial {
+                base_color: self.color,
+                unlit: true,
+                cull_mode: None,
+                ..Default::default()
+            },
+            _ => StandardMaterial::from_color(self.color),
+        };
+        let material_handle = world.resource_mut::<Assets<StandardMaterial>>().add(material);
+
+        // The stroke stays hidden until playback reaches its action.
+        world.spawn((
+            StrokeSegment {
+                action: self.action,
+                stroke: self.stroke,
+                progress: 0.0,
+                path: self.path,
+            },
+            Mesh3d(mesh_handle),
+            MeshMaterial3d(material_handle),
+            Transform::IDENTITY,
+            Visibility::Hidden,
+        ));
+    }
+}
+
+pub(crate) fn make_stroke_mesh(stroke: Stroke, path: &[Transform]) -> MeshBuffer {
+    match stroke {
+        Stroke::Volume(diameter) => {
+            let points: Vec<Vec3> = path.iter().map(|tf| tf.translation).collect();
+            make_volume_stroke(diameter / 2.0, &points)
+        }
+        Stroke::Ribbon(width) => make_ribbon_stroke(width, path),
+        // The real geometry depends on the camera, so this is only a
+        // placeholder until update_stroke_meshes gets to it.
+        Stroke::Pixels(width) => {
+            let points: Vec<Vec3> = path.iter().map(|tf| tf.translation).collect();
+            make_pixel_stroke(width, &points, None)
+        }
+    }
+}
+
+pub(crate) fn update_stroke_meshes(
+    strokes: Query<(Ref<StrokeSegment>, &Mesh3d)>,
+    main_camera: Res<MainCamera>,
+    cameras: Query<(Ref<Camera>, Ref<GlobalTransform>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let view = cameras.get(main_camera.entity).ok();
+    let view_changed = view
+        .as_ref()
+        .is_some_and(|(camera, tf)| camera.is_changed() || tf.is_changed());
+
+    for (segment, mesh) in &strokes {
+        let buffer = match segment.stroke {
+            Stroke::Pixels(width) => {
+                if !segment.is_changed() && !view_changed {
+                    continue;
+                }
+
+                let points: Vec<Vec3> = segment.path.iter().map(|tf| tf.translation).collect();
+                let view = view.as_ref().map(|(camera, tf)| (&**camera, &**tf));
+                make_pixel_stroke(width, &points, view)
+            }
+            stroke => {
+                if !segment.is_changed() {
+                    continue;
+                }
+
+                make_stroke_mesh(stroke, &segment.path)
+            }
+        };
+
+        if let Some(mesh) = meshes.get_mut(&mesh.0) {
+            *mesh = buffer.into();
+        }
+    }
+}
+
+/// Build camera-facing quads whose width on screen is `width` logical pixels,
+/// no matter how far away from the camera the line is.
+fn make_pixel_stroke(
+    width: u32,
+    path: &[Vec3],
+    view: Option<(&Camera, &GlobalTransform)>,
+) -> MeshBuffer {
+    let mut buffer = MeshBuffer::empty();
+    for segment in path.windows(2) {
+        let [start, end] = [segment[0], segment[1]];
+        let corners = view
+            .and_then(|(camera, camera_tf)| {
+                screen_space_quad(start, end, width as f32, camera, camera_tf)
+            })
+            // Without a view to measure pixels against, leave a sliver that
+            // will be filled in once the camera is known.
+            .unwrap_or([start, start, end, end]);
+
+        let normal: [f32; 3] = view
+            .map(|(_, camera_tf)| camera_tf.back().as_vec3().into())
+            .unwrap_or([0., 0., 1.]);
+        let positions: Vec<[f32; 3]> = corners.into_iter().map(Into::into).collect();
+        let normals = vec![normal; positions.len()];
+        buffer = buffer.merge_with(MeshBuffer::new(positions, normals, vec![0, 1, 2, 1, 3, 2]));
+    }
+
+    buffer
+}
+
+fn screen_space_quad(
+    start: Vec3,
+    end: Vec3,
+    width: f32,
+    camera: &Camera,
+    camera_tf: &GlobalTransform,
+) -> Option<[Vec3; 4]> {
+    let viewport = camera.logical_viewport_size()?;
+
+    // Lines that pass behind the camera cannot be projected sensibly.
+    let view_from_world = camera_tf.affine().inverse();
+    if [start, end].iter().any(|p| view_from_world.transform_point3(*p).z >= 0.0) {
+        return None;
+    }
+
+    let ndc_start = camera.world_to_ndc(camera_tf, start)?;
+    let ndc_end = camera.world_to_ndc(camera_tf, end)?;
+
+    // Work out the direction of the line in pixels, then step half the width
+    // to either side of it. Extend each end by the same amount so that
+    // consecutive segments overlap at their joints.
+    let pixel_dir = ((ndc_end - ndc_start).truncate() * viewport / 2.0).try_normalize()?;
+    let to_ndc = |pixels: Vec2| (pixels * 2.0 / viewport).extend(0.0);
+    let side = to_ndc(width / 2.0 * pixel_dir.perp());
+    let along = to_ndc(width / 2.0 * pixel_dir);
+
+    Some([
+        camera.ndc_to_world(camera_tf, ndc_start - along - side)?,
+        camera.ndc_to_world(camera_tf, ndc_start - along + side)?,
+        camera.ndc_to_world(camera_tf, ndc_end + along - side)?,
+        camera.ndc_to_world(camera_tf, ndc_end + along + side)?,
+    ])
+}
+
+fn make_ribbon_stroke(width: f32, path: &[Transform]) -> MeshBuffer {
+    let resolution = 32;
+    let mut buffer = make_ribbon(path, width);
+
+    // Round off each end of the ribbon with a disk that lies flat in the
+    // plane of the ribbon.
+    for pose in [path.first(), path.last()].into_iter().flatten() {
+        buffer = buffer.merge_with(
+            make_flat_disk(Circle { radius: width / 2.0, height: 0.0 }, resolution)
+                .transform_by(Affine3A::from_rotation_translation(pose.rotation, pose.translation))
+        );
+    }
+
+    buffer
+}
+
+fn make_volume_stroke(radius: f32, path: &[Vec3]) -> MeshBuffer {
+    let resolution = 32;
+    let mut buffer = MeshBuffer::empty();
+
+    // Cap each end of the path with a sphere so that consecutive strokes
+    // appear to be joined smoothly.
+    for p in [path.first(), path.last()].into_iter().flatten() {
+        buffer = buffer.merge_with(
+            make_sphere(radius, resolution)
+                .transform_by(Affine3A::from_translation(*p))
+        );
+    }
+
+    for segment in path.windows(2) {
+        let [start, end] = [segment[0], segment[1]];
+        if (end - start).length_squared() <= f32::EPSILON {
+            continue;
+        }
+
+        buffer = buffer.merge_with(make_tube(start, end, radius, resolution));
+    }
+
+    buffer
+}
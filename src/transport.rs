@@ -0,0 +1,361 @@
+/*
+ * Copyright (C) 2024 Michael X. Grey
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+
+use bevy::{
+    prelude::{
+        BackgroundColor, BuildChildren, Button, ButtonInput, ChildBuild, Changed, Children,
+        Color, Commands, Component, DespawnRecursiveExt, DetectChanges, Entity, Interaction,
+        KeyCode, Node, PositionType, Query, Res, ResMut, TargetCamera, Text, TextFont, Val,
+        With, Without, World, AlignItems, JustifyContent, UiRect,
+    },
+    ui::RelativeCursorPosition,
+};
+
+use crate::{Playback, Timeline};
+
+pub(crate) const PLAY_PAUSE_KEY: KeyCode = KeyCode::Space;
+pub(crate) const STEP_FORWARD_KEY: KeyCode = KeyCode::Period;
+pub(crate) const STEP_BACK_KEY: KeyCode = KeyCode::Comma;
+pub(crate) const FASTER_KEY: KeyCode = KeyCode::BracketRight;
+pub(crate) const SLOWER_KEY: KeyCode = KeyCode::BracketLeft;
+pub(crate) const RESTART_KEY: KeyCode = KeyCode::KeyR;
+
+const MIN_SPEED: f32 = 1.0 / 16.0;
+const MAX_SPEED: f32 = 16.0;
+
+const TRANSPORT_HEIGHT: f32 = 36.0;
+const TRANSPORT_FONT_SIZE: f32 = 14.0;
+const TRANSPORT_BACKGROUND: Color = Color::srgba(0.0, 0.0, 0.0, 0.6);
+const BUTTON_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const BUTTON_HOVER_COLOR: Color = Color::srgb(0.35, 0.35, 0.35);
+const TRACK_COLOR: Color = Color::srgb(0.2, 0.2, 0.2);
+const FILL_COLOR: Color = Color::srgb(0.3, 0.5, 0.9);
+const TICK_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.5);
+
+/// Something that the transport can do to the playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransportAction {
+    Restart,
+    StepBack,
+    PlayPause,
+    StepForward,
+    Slower,
+    Faster,
+}
+
+impl TransportAction {
+    const ALL: [TransportAction; 6] = [
+        TransportAction::Restart,
+        TransportAction::StepBack,
+        TransportAction::PlayPause,
+        TransportAction::StepForward,
+        TransportAction::Slower,
+        TransportAction::Faster,
+    ];
+
+    fn apply(self, playback: &mut Playback, timeline: &Timeline) {
+        match self {
+            TransportAction::Restart => playback.restart(),
+            TransportAction::StepBack => playback.step_back(timeline),
+            TransportAction::PlayPause => {
+                if playback.time >= timeline.duration() {
+                    // Playing a finished sketch starts it over, whether or
+                    // not it was paused when it finished.
+                    playback.restart();
+                } else {
+                    playback.paused = !playback.paused;
+                }
+            }
+            TransportAction::StepForward => playback.step_forward(timeline),
+            TransportAction::Slower => {
+                playback.speed = (playback.speed / 2.0).max(MIN_SPEED);
+            }
+            TransportAction::Faster => {
+                playback.speed = (playback.speed * 2.0).min(MAX_SPEED);
+            }
+        }
+    }
+
+    fn key(self) -> KeyCode {
+        match self {
+            TransportAction::Restart => RESTART_KEY,
+            TransportAction::StepBack => STEP_BACK_KEY,
+            TransportAction::PlayPause => PLAY_PAUSE_KEY,
+            TransportAction::StepForward => STEP_FORWARD_KEY,
+            TransportAction::Slower => SLOWER_KEY,
+            TransportAction::Faster => FASTER_KEY,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            TransportAction::Restart => "<<",
+            TransportAction::StepBack => "<|",
+            TransportAction::PlayPause => "||",
+            TransportAction::StepForward => "|>",
+            TransportAction::Slower => "-",
+            TransportAction::Faster => "+",
+        }
+    }
+}
+
+#[derive(Debug, Component)]
+pub(crate) struct TransportButton(TransportAction);
+
+/// The track that can be clicked or dragged to jump to any moment.
+#[derive(Debug, Component)]
+pub(crate) struct Scrubber;
+
+/// The part of the scrubber that shows how much has been played.
+#[derive(Debug, Component)]
+pub(crate) struct ScrubberFill;
+
+/// Parent of the marks on the scrubber where each action starts.
+#[derive(Debug, Component)]
+pub(crate) struct ScrubberTicks;
+
+#[derive(Debug, Component)]
+pub(crate) struct PlayPauseLabel;
+
+#[derive(Debug, Component)]
+pub(crate) struct TransportClock;
+
+/// Put a transport bar along the bottom of what the camera sees.
+///
+/// * Play or pause: `Space`
+/// * Step to the next or previous action: `.` and `,`
+/// * Play slower or faster: `[` and `]`
+/// * Restart: `R`
+/// * Jump to any moment: click or drag along the timeline
+pub(crate) fn spawn_transport(world: &mut World, camera: Entity) {
+    let font = TextFont {
+        font_size: TRANSPORT_FONT_SIZE,
+        ..Default::default()
+    };
+
+    world.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            bottom: Val::Px(0.0),
+            height: Val::Px(TRANSPORT_HEIGHT),
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(4.0),
+            padding: UiRect::horizontal(Val::Px(8.0)),
+            ..Default::default()
+        },
+        BackgroundColor(TRANSPORT_BACKGROUND),
+        TargetCamera(camera),
+    )).with_children(|bar| {
+        let button_node = Node {
+            width: Val::Px(28.0),
+            height: Val::Px(24.0),
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        };
+
+        for action in TransportAction::ALL {
+            bar.spawn((
+                TransportButton(action),
+                Button,
+                button_node.clone(),
+                BackgroundColor(BUTTON_COLOR),
+            )).with_children(|button| {
+                let mut label = button.spawn((Text::new(action.label()), font.clone()));
+                if action == TransportAction::PlayPause {
+                    label.insert(PlayPauseLabel);
+                }
+            });
+        }
+
+        bar.spawn((
+            Scrubber,
+            Button,
+            RelativeCursorPosition::default(),
+            Node {
+                flex_grow: 1.0,
+                height: Val::Px(12.0),
+                margin: UiRect::horizontal(Val::Px(8.0)),
+                ..Default::default()
+            },
+            BackgroundColor(TRACK_COLOR),
+        )).with_children(|track| {
+            track.spawn((
+                ScrubberFill,
+                Node {
+                    width: Val::Percent(0.0),
+                    height: Val::Percent(100.0),
+                    ..Default::default()
+                },
+                BackgroundColor(FILL_COLOR),
+            ));
+            track.spawn((
+                ScrubberTicks,
+                Node {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..Default::default()
+                },
+            ));
+        });
+
+        bar.spawn((TransportClock, Text::new(""), font));
+    });
+}
+
+pub(crate) fn control_playback(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<Playback>,
+    timeline: Res<Timeline>,
+    mut buttons: Query<
+        (&TransportButton, &Interaction, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    scrubbers: Query<(&Interaction, &RelativeCursorPosition), With<Scrubber>>,
+) {
+    for (button, interaction, mut color) in &mut buttons {
+        match interaction {
+            Interaction::Pressed => button.0.apply(&mut playback, &timeline),
+            Interaction::Hovered => color.0 = BUTTON_HOVER_COLOR,
+            Interaction::None => color.0 = BUTTON_COLOR,
+        }
+    }
+
+    for action in TransportAction::ALL {
+        if keyboard.just_pressed(action.key()) {
+            action.apply(&mut playback, &timeline);
+        }
+    }
+
+    for (interaction, cursor) in &scrubbers {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        if let Some(cursor) = cursor.normalized {
+            // Scrubbing holds the playback still so the moment can be studied.
+            playback.paused = true;
+            playback.time = cursor.x.clamp(0.0, 1.0) * timeline.duration();
+        }
+    }
+}
+
+pub(crate) fn update_transport(
+    mut commands: Commands,
+    playback: Res<Playback>,
+    timeline: Res<Timeline>,
+    mut fills: Query<&mut Node, With<ScrubberFill>>,
+    ticks: Query<(Entity, Option<&Children>), With<ScrubberTicks>>,
+    mut play_pause: Query<&mut Text, (With<PlayPauseLabel>, Without<TransportClock>)>,
+    mut clocks: Query<&mut Text, (With<TransportClock>, Without<PlayPauseLabel>)>,
+) {
+    let duration = timeline.duration();
+    let fraction = if duration > 0.0 {
+        (playback.time / duration).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+
+    for mut node in &mut fills {
+        let width = Val::Percent(100.0 * fraction);
+        if node.width != width {
+            node.width = width;
+        }
+    }
+
+    let finished = playback.time >= duration;
+    let label = if playback.paused || finished { ">" } else { "||" };
+    for mut text in &mut play_pause {
+        if text.0 != label {
+            text.0 = label.to_owned();
+        }
+    }
+
+    let clock = format!(
+        "{:.2} / {:.2} s  x{}",
+        playback.time.min(duration),
+        duration,
+        playback.speed,
+    );
+    for mut text in &mut clocks {
+        if text.0 != clock {
+            text.0 = clock.clone();
+        }
+    }
+
+    if !timeline.is_changed() || duration <= 0.0 {
+        return;
+    }
+
+    // Mark where each action starts so steps can be lined up with the code.
+    let mut starts: Vec<f32> = timeline
+        .time_points
+        .iter()
+        .map(|t| (1000.0 * t.start / duration).round() / 10.0)
+        .collect();
+    starts.sort_by(f32::total_cmp);
+    starts.dedup();
+
+    for (entity, children) in &ticks {
+        for child in children.into_iter().flatten() {
+            commands.entity(*child).despawn_recursive();
+        }
+
+        commands.entity(entity).with_children(|parent| {
+            for percent in &starts {
+                parent.spawn((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Percent(*percent),
+                        width: Val::Px(1.0),
+                        height: Val::Percent(100.0),
+                        ..Default::default()
+                    },
+                    BackgroundColor(TICK_COLOR),
+                ));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_on_a_finished_sketch_starts_it_over() {
+        let mut timeline = Timeline::default();
+        timeline.extend_to(4.0);
+
+        for paused in [false, true] {
+            let mut playback = Playback { time: 4.0, paused, ..Default::default() };
+            TransportAction::PlayPause.apply(&mut playback, &timeline);
+            assert_eq!(playback.time, 0.0);
+            assert!(!playback.paused);
+        }
+
+        let mut playback = Playback { time: 2.0, ..Default::default() };
+        TransportAction::PlayPause.apply(&mut playback, &timeline);
+        assert!(playback.paused);
+        TransportAction::PlayPause.apply(&mut playback, &timeline);
+        assert!(!playback.paused);
+        assert_eq!(playback.time, 2.0);
+    }
+}